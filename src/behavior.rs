@@ -1,44 +1,130 @@
-use core::cell::Cell;
+use core::cmp::Ordering;
 
 use crate::{
+    config::EngineConfig,
+    error::Error,
     event::{BehaviorEvent, Event, EventData, KeyEvent, LayerEvent, SimpleKeyEvent},
     layer::Layer,
-    timer::Duration,
-    vboard::Key,
+    registry::BehaviorId,
+    timer::{Duration, Instant},
+    vboard::{Key, KeyCode, Modifiers},
 };
 
+/// Behaviors don't know their own identity, `id` is the handle of the behavior instance that is
+/// running them, and is attached to the events they produce
 pub trait BehaviorSimple {
-    fn on_activate(&self) -> Option<Event>;
-    fn on_deactivate(&self) -> Option<Event>;
-    fn behavior_id(&self) -> usize;
+    fn on_activate(&self, id: BehaviorId) -> Option<Event>;
+    fn on_deactivate(&self, id: BehaviorId) -> Option<Event>;
 }
-pub trait BehaviorComplex {
-    fn on_press(&mut self) -> Option<Event>;
-    fn on_unpress(&mut self) -> Option<Event>;
-    fn get_duration(&self) -> Option<Duration>;
-    fn on_timeout(&mut self) -> Option<Event>;
-    fn id(&self) -> usize;
+/// What the engine knows about a key press when it hands it to a behavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PressContext {
+    pub instant: Instant,
+    /// Instant of the previous key press, at any position
+    pub last_press: Option<Instant>,
+    /// The last key sent to the host that counts for key repeat (see `State::set_repeatable`),
+    /// with the modifiers that were held along with it
+    pub last_key: Option<KeyCode>,
 }
 
-impl BehaviorComplex for SimpleBehavior {
-    fn on_press(&mut self) -> Option<Event> {
-        self.on_activate()
+/// Behavior bound to a key. The event methods return an error if the behavior's state doesn't
+/// allow the transition, e.g. an unpress it has no way of handling.
+pub trait BehaviorComplex {
+    fn on_press(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<Option<Event>, Error>;
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error>;
+    /// How long after a press, unpress or timeout `on_timeout` should be called, if at all. Asked
+    /// again after each of them (and after watched key events with `rearms_on_key_event`), the
+    /// new answer replaces any timeout that is still pending.
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration>;
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error>;
+
+    /// Whether the behavior can be dropped once its key is released, asked again after every
+    /// timeout until it is. While it isn't, the next press of the same key on the same layer goes
+    /// to this instance instead of a fresh copy from the keymap.
+    fn is_done(&self) -> bool {
+        true
     }
 
-    fn on_unpress(&mut self) -> Option<Event> {
-        self.on_deactivate()
+    /// Whether the behavior is still waiting to decide what it does. Undecided behaviors are told
+    /// about other keys, and hold back their processing until they decide.
+    fn is_undecided(&self) -> bool {
+        false
     }
 
-    fn get_duration(&self) -> Option<Duration> {
-        None
+    /// Whether the behavior wants to be told about other keys even though it has decided. A key
+    /// that was held back while it was undecided can then be reported to it a second time, when
+    /// the key is processed.
+    fn is_listening(&self) -> bool {
+        false
+    }
+
+    /// Whether the behavior wants `on_key_event` for the keys other behaviors press and release
+    fn watches_keys(&self) -> bool {
+        false
+    }
+
+    /// Another behavior (`source`) pressed or released a key, after it was applied
+    fn on_key_event(
+        &mut self,
+        _id: BehaviorId,
+        _event: &SimpleKeyEvent,
+        _source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        Ok(None)
+    }
+
+    /// Whether the timeout is armed again after every `on_key_event`, for timeouts that count
+    /// from the last key typed rather than from the behavior's own key
+    fn rearms_on_key_event(&self) -> bool {
+        false
+    }
+
+    /// Whether a press of `code` by another behavior goes only to this one's `on_key_event`
+    /// instead of the host, while it watches keys. Its release is dropped as well.
+    fn captures_key(&self, _code: &KeyCode) -> bool {
+        false
+    }
+
+    /// Modifiers to press along with a key another behavior is about to press, while this one
+    /// watches keys. They are released together with the key.
+    fn extra_modifiers(&self, _code: &KeyCode) -> Modifiers {
+        Modifiers::NONE
+    }
+
+    /// Another key position was pressed while this behavior is undecided or listening
+    fn on_other_press(
+        &mut self,
+        _id: BehaviorId,
+        _position: usize,
+    ) -> Result<Option<Event>, Error> {
+        Ok(None)
+    }
+
+    /// Another key position was released while this behavior is undecided or listening
+    fn on_other_release(
+        &mut self,
+        _id: BehaviorId,
+        _position: usize,
+    ) -> Result<Option<Event>, Error> {
+        Ok(None)
+    }
+}
+
+impl BehaviorComplex for SimpleBehavior {
+    fn on_press(&mut self, id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
+        Ok(self.on_activate(id))
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        Ok(self.on_deactivate(id))
     }
 
-    fn on_timeout(&mut self) -> Option<Event> {
+    fn get_duration(&self, _config: &EngineConfig) -> Option<Duration> {
         None
     }
 
-    fn id(&self) -> usize {
-        self.behavior_id()
+    fn on_timeout(&mut self, _id: BehaviorId) -> Result<Option<Event>, Error> {
+        Ok(None)
     }
 }
 
@@ -50,230 +136,1542 @@ pub enum SimpleBehavior {
     MomentaryLayer(MomentaryLayerBehavior),
 }
 impl BehaviorSimple for SimpleBehavior {
-    fn on_activate(&self) -> Option<Event> {
-        match self {
-            SimpleBehavior::KeyPress(b) => b.on_activate(),
-            SimpleBehavior::MomentaryLayer(b) => b.on_activate(),
-        }
-    }
-
-    fn on_deactivate(&self) -> Option<Event> {
+    fn on_activate(&self, id: BehaviorId) -> Option<Event> {
         match self {
-            SimpleBehavior::KeyPress(b) => b.on_deactivate(),
-            SimpleBehavior::MomentaryLayer(b) => b.on_deactivate(),
+            SimpleBehavior::KeyPress(b) => b.on_activate(id),
+            SimpleBehavior::MomentaryLayer(b) => b.on_activate(id),
         }
     }
 
-    fn behavior_id(&self) -> usize {
+    fn on_deactivate(&self, id: BehaviorId) -> Option<Event> {
         match self {
-            SimpleBehavior::KeyPress(e) => e.behavior_id(),
-            SimpleBehavior::MomentaryLayer(e) => e.behavior_id(),
+            SimpleBehavior::KeyPress(b) => b.on_deactivate(id),
+            SimpleBehavior::MomentaryLayer(b) => b.on_deactivate(id),
         }
     }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct KeyPressBehavior {
-    key: Key,
-    behavior_id: usize,
+    key: KeyCode,
+}
+
+impl KeyPressBehavior {
+    pub const fn new(key: Key) -> Self {
+        Self {
+            key: KeyCode::new(key),
+        }
+    }
+
+    /// Press a key together with modifiers, e.g. `Modifiers::LEFT_CTRL` and `Key::C` for copy
+    pub const fn with_code(key: KeyCode) -> Self {
+        Self { key }
+    }
 }
 
 impl BehaviorSimple for KeyPressBehavior {
-    fn on_activate(&self) -> Option<Event> {
+    fn on_activate(&self, id: BehaviorId) -> Option<Event> {
         Some(Event::new(
-            self.behavior_id,
+            id,
             EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Press(self.key))),
         ))
     }
 
-    fn on_deactivate(&self) -> Option<Event> {
+    fn on_deactivate(&self, id: BehaviorId) -> Option<Event> {
         Some(Event::new(
-            self.behavior_id,
+            id,
             EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Unpress(self.key))),
         ))
     }
-
-    fn behavior_id(&self) -> usize {
-        self.behavior_id
-    }
 }
 
 #[derive(Debug, Clone, Copy)]
-/// Holds both "from" layer and "to" layer because when released, it needs to pop the layer
-/// stack down to the "from" layer.
+/// Only removes its own layer when released, so layers activated on top of it (or below it)
+/// while it is held stay active for as long as their own keys are held.
 pub struct MomentaryLayerBehavior {
-    layer_from: Layer,
-    layer_to: Layer,
-    behavior_id: usize,
+    layer: Layer,
+}
+
+impl MomentaryLayerBehavior {
+    pub const fn new(layer: Layer) -> Self {
+        Self { layer }
+    }
 }
 impl BehaviorSimple for MomentaryLayerBehavior {
-    fn on_activate(&self) -> Option<Event> {
+    fn on_activate(&self, id: BehaviorId) -> Option<Event> {
         Some(Event::new(
-            self.behavior_id,
-            EventData::LayerEvent(LayerEvent::AddLayer(self.layer_to)),
+            id,
+            EventData::LayerEvent(LayerEvent::AddLayer(self.layer)),
         ))
     }
 
-    fn on_deactivate(&self) -> Option<Event> {
+    fn on_deactivate(&self, id: BehaviorId) -> Option<Event> {
         Some(Event::new(
-            self.behavior_id,
-            EventData::LayerEvent(LayerEvent::RemoveToLayer(self.layer_from)),
+            id,
+            EventData::LayerEvent(LayerEvent::RemoveLayer(self.layer)),
         ))
     }
-
-    fn behavior_id(&self) -> usize {
-        self.behavior_id
-    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ManualBehavior {
     HoldTap(HoldTapBehavior),
+    TapDance(TapDanceBehavior),
+    StickyKey(StickyKeyBehavior),
+    OneShotLayer(OneShotLayerBehavior),
+    CapsWord(CapsWordBehavior),
+    KeyRepeat(KeyRepeatBehavior),
+    Macro(MacroBehavior),
+    Leader(LeaderBehavior),
     Simple(SimpleBehavior),
 }
 
-impl BehaviorComplex for ManualBehavior {
-    fn on_press(&mut self) -> Option<Event> {
-        match self {
-            ManualBehavior::HoldTap(b) => b.on_press(),
-            ManualBehavior::Simple(b) => b.on_press(),
+/// Forward a call to the behavior a `ManualBehavior` holds, `$b` is bound to it in `$call`
+macro_rules! dispatch {
+    ($self:expr, $b:ident => $call:expr) => {
+        match $self {
+            ManualBehavior::HoldTap($b) => $call,
+            ManualBehavior::TapDance($b) => $call,
+            ManualBehavior::StickyKey($b) => $call,
+            ManualBehavior::OneShotLayer($b) => $call,
+            ManualBehavior::CapsWord($b) => $call,
+            ManualBehavior::KeyRepeat($b) => $call,
+            ManualBehavior::Macro($b) => $call,
+            ManualBehavior::Leader($b) => $call,
+            ManualBehavior::Simple($b) => $call,
         }
+    };
+}
+
+impl BehaviorComplex for ManualBehavior {
+    fn on_press(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_press(id, ctx))
     }
 
-    fn on_unpress(&mut self) -> Option<Event> {
-        match self {
-            ManualBehavior::HoldTap(b) => b.on_unpress(),
-            ManualBehavior::Simple(b) => b.on_unpress(),
-        }
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_unpress(id))
     }
 
-    fn get_duration(&self) -> Option<Duration> {
-        match self {
-            ManualBehavior::HoldTap(b) => b.get_duration(),
-            ManualBehavior::Simple(b) => b.get_duration(),
-        }
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        dispatch!(self, b => b.get_duration(config))
     }
 
-    fn on_timeout(&mut self) -> Option<Event> {
-        match self {
-            ManualBehavior::HoldTap(b) => b.on_timeout(),
-            ManualBehavior::Simple(b) => b.on_timeout(),
-        }
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_timeout(id))
     }
 
-    fn id(&self) -> usize {
-        match self {
-            ManualBehavior::HoldTap(b) => b.id(),
-            ManualBehavior::Simple(b) => b.behavior_id(),
-        }
+    fn is_undecided(&self) -> bool {
+        dispatch!(self, b => b.is_undecided())
+    }
+
+    fn is_done(&self) -> bool {
+        dispatch!(self, b => b.is_done())
+    }
+
+    fn is_listening(&self) -> bool {
+        dispatch!(self, b => b.is_listening())
+    }
+
+    fn watches_keys(&self) -> bool {
+        dispatch!(self, b => b.watches_keys())
+    }
+
+    fn on_key_event(
+        &mut self,
+        id: BehaviorId,
+        event: &SimpleKeyEvent,
+        source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_key_event(id, event, source))
+    }
+
+    fn rearms_on_key_event(&self) -> bool {
+        dispatch!(self, b => b.rearms_on_key_event())
+    }
+
+    fn captures_key(&self, code: &KeyCode) -> bool {
+        dispatch!(self, b => b.captures_key(code))
+    }
+
+    fn extra_modifiers(&self, code: &KeyCode) -> Modifiers {
+        dispatch!(self, b => b.extra_modifiers(code))
+    }
+
+    fn on_other_press(&mut self, id: BehaviorId, position: usize) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_other_press(id, position))
+    }
+
+    fn on_other_release(
+        &mut self,
+        id: BehaviorId,
+        position: usize,
+    ) -> Result<Option<Event>, Error> {
+        dispatch!(self, b => b.on_other_release(id, position))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HoldTapBehaviorState {
     Pending,
     DecidedTap,
     DecidedHold,
+    /// Released after a tap that is still active, it ends on timeout or when pressed again
+    Tapping,
+    /// Released after a tap, pressing it again within the quick-tap window taps again
+    Tapped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How a pending hold-tap reacts to other keys, same semantics as ZMK's hold-tap flavors
+pub enum HoldTapFlavor {
+    /// Hold as soon as another key is pressed, or on timeout
+    HoldPreferred,
+    /// Hold if another key is pressed and released while pending, or on timeout
+    Balanced,
+    /// Other keys don't matter, hold only on timeout
+    #[default]
+    TapPreferred,
+    /// Hold as soon as another key is pressed, tap on timeout
+    TapUnlessInterrupted,
 }
-#[derive(Debug)]
+
+/// How many keys pressed while a hold-tap is pending it remembers, for the balanced flavor
+pub const MAX_HOLD_TAP_INTERRUPTS: usize = 4;
+
+#[derive(Debug, Clone)]
 pub struct HoldTapBehavior {
-    id: usize,
     state: HoldTapBehaviorState,
     hold: SimpleBehavior,
     tap: SimpleBehavior,
-    timeout: Duration,
+    flavor: HoldTapFlavor,
+    // Overrides for the `EngineConfig` defaults
+    timeout: Option<Duration>,
+    tap_duration: Option<Duration>,
     hold_while_undecided: bool,
+    quick_tap: Option<Duration>,
+    require_prior_idle: Option<Duration>,
+    hold_trigger_positions: Option<&'static [usize]>,
+    hold_trigger_on_release: bool,
+    retro_tap: bool,
+    // Whether the last tap was still active when the key was released, so part of the quick-tap
+    // window has already passed once it ends
+    tapping_after_release: bool,
+    // Whether another key was pressed since this one
+    interrupted: bool,
+    // Positions pressed while pending
+    interrupts: [Option<usize>; MAX_HOLD_TAP_INTERRUPTS],
 }
 
 impl HoldTapBehavior {
-    pub fn new(
-        hold: SimpleBehavior,
-        tap: SimpleBehavior,
-        timeout: Duration,
-        hold_while_undecided: bool,
-    ) -> Self {
+    pub const fn new(hold: SimpleBehavior, tap: SimpleBehavior) -> Self {
         Self {
-            id: get_behavior_id(),
             state: HoldTapBehaviorState::Pending,
             tap,
             hold,
-            timeout,
-            hold_while_undecided,
+            flavor: HoldTapFlavor::TapPreferred,
+            timeout: None,
+            tap_duration: None,
+            hold_while_undecided: false,
+            quick_tap: None,
+            require_prior_idle: None,
+            hold_trigger_positions: None,
+            hold_trigger_on_release: false,
+            retro_tap: false,
+            tapping_after_release: false,
+            interrupted: false,
+            interrupts: [None; MAX_HOLD_TAP_INTERRUPTS],
         }
     }
-}
 
-impl BehaviorComplex for HoldTapBehavior {
-    fn on_press(&mut self) -> Option<Event> {
+    pub const fn with_flavor(mut self, flavor: HoldTapFlavor) -> Self {
+        self.flavor = flavor;
+        self
+    }
+
+    /// Decide on hold after `timeout` instead of `EngineConfig::hold_tap_timeout`
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Hold the tap behavior for `tap_duration` instead of `EngineConfig::tap_duration`
+    pub const fn with_tap_duration(mut self, tap_duration: Duration) -> Self {
+        self.tap_duration = Some(tap_duration);
+        self
+    }
+
+    /// Activate the hold behavior as soon as the key is pressed, and release it if the key turns
+    /// out to be a tap
+    pub const fn with_hold_while_undecided(mut self, hold_while_undecided: bool) -> Self {
+        self.hold_while_undecided = hold_while_undecided;
+        self
+    }
+
+    /// Pressing the key again within `quick_tap` of releasing it after a tap always taps, and keeps
+    /// the tap behavior active until release. Holding the key then auto-repeats the tap instead of
+    /// activating the hold behavior. A press while the tap is still active always counts, and ends
+    /// the tap first so the host sees two keystrokes.
+    pub const fn with_quick_tap(mut self, quick_tap: Duration) -> Self {
+        self.quick_tap = Some(quick_tap);
+        self
+    }
+
+    /// Tap right away if another key was pressed less than `require_prior_idle` before this one.
+    /// Stops hold-taps on letter keys from turning into holds while typing fast.
+    pub const fn with_require_prior_idle(mut self, require_prior_idle: Duration) -> Self {
+        self.require_prior_idle = Some(require_prior_idle);
+        self
+    }
+
+    /// Only keys at these positions can make the hold-tap a hold, any other key interrupting it
+    /// makes it a tap. Usually the positions on the other hand, for home row mods.
+    pub const fn with_hold_trigger_positions(mut self, positions: &'static [usize]) -> Self {
+        self.hold_trigger_positions = Some(positions);
+        self
+    }
+
+    /// Decide on interrupting keys when they are released rather than pressed, so several
+    /// hold-taps on the same hand can be held together before the key they modify
+    pub const fn with_hold_trigger_on_release(mut self, hold_trigger_on_release: bool) -> Self {
+        self.hold_trigger_on_release = hold_trigger_on_release;
+        self
+    }
+
+    /// Tap when released after deciding on hold, as long as no other key was pressed in the
+    /// meantime
+    pub const fn with_retro_tap(mut self, retro_tap: bool) -> Self {
+        self.retro_tap = retro_tap;
+        self
+    }
+
+    pub fn state(&self) -> HoldTapBehaviorState {
+        self.state
+    }
+
+    fn decide_hold(&mut self, id: BehaviorId) -> Option<Event> {
+        self.state = HoldTapBehaviorState::DecidedHold;
         if self.hold_while_undecided {
+            None
+        } else {
             Some(Event::new(
-                self.id,
+                id,
                 EventData::BehaviorEvent(BehaviorEvent::StartBehavior(self.hold)),
             ))
+        }
+    }
+
+    /// Decide on tap while the key is still held, the tap behavior stays active until release
+    fn decide_tap_held(&mut self, id: BehaviorId) -> Option<Event> {
+        self.state = HoldTapBehaviorState::DecidedTap;
+        if self.hold_while_undecided {
+            Some(Event::new(
+                id,
+                EventData::BehaviorEvent(BehaviorEvent::ReleasePressBehavior(self.hold, self.tap)),
+            ))
         } else {
-            None
+            Some(Event::new(
+                id,
+                EventData::BehaviorEvent(BehaviorEvent::StartBehavior(self.tap)),
+            ))
         }
     }
 
-    fn on_unpress(&mut self) -> Option<Event> {
+    fn is_hold_trigger(&self, position: usize) -> bool {
+        self.hold_trigger_positions
+            .is_none_or(|positions| positions.contains(&position))
+    }
+
+    /// State once a tap is released
+    fn tapped_state(&self) -> HoldTapBehaviorState {
+        if self.quick_tap.is_some() {
+            HoldTapBehaviorState::Tapped
+        } else {
+            HoldTapBehaviorState::DecidedTap
+        }
+    }
+
+    /// Tap once the key is released, releasing `held` first. With quick-tap the tap is ended by
+    /// this behavior's timeout rather than by the engine, so a quick re-press can end it early.
+    fn tap_released(&mut self, id: BehaviorId, held: Option<SimpleBehavior>) -> Event {
+        let event = match (self.quick_tap, held) {
+            (Some(_), Some(held)) => BehaviorEvent::ReleasePressBehavior(held, self.tap),
+            (Some(_), None) => BehaviorEvent::StartBehavior(self.tap),
+            (None, Some(held)) => {
+                BehaviorEvent::ReleaseTapBehavior(held, self.tap, self.tap_duration)
+            }
+            (None, None) => BehaviorEvent::TapBehavior(self.tap, self.tap_duration),
+        };
+        self.state = if self.quick_tap.is_some() {
+            HoldTapBehaviorState::Tapping
+        } else {
+            HoldTapBehaviorState::DecidedTap
+        };
+        self.tapping_after_release = true;
+
+        Event::new(id, EventData::BehaviorEvent(event))
+    }
+}
+
+impl BehaviorComplex for HoldTapBehavior {
+    fn on_press(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<Option<Event>, Error> {
+        let tapping = self.state == HoldTapBehaviorState::Tapping;
+        let quick_tap = tapping || self.state == HoldTapBehaviorState::Tapped;
+        let typing = self
+            .require_prior_idle
+            .zip(ctx.last_press)
+            .is_some_and(|(idle, last)| ctx.instant - last < idle);
+
+        if quick_tap || typing {
+            self.state = HoldTapBehaviorState::DecidedTap;
+            let event = if tapping {
+                // End the last tap first, or the host never sees this one
+                BehaviorEvent::ReleasePressBehavior(self.tap, self.tap)
+            } else {
+                BehaviorEvent::StartBehavior(self.tap)
+            };
+            return Ok(Some(Event::new(id, EventData::BehaviorEvent(event))));
+        }
+
+        if self.hold_while_undecided {
+            Ok(Some(Event::new(
+                id,
+                EventData::BehaviorEvent(BehaviorEvent::StartBehavior(self.hold)),
+            )))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
         match self.state {
+            // Decided on tap while held, the tap behavior is still active
             HoldTapBehaviorState::DecidedTap => {
-                panic!("Invalid state: DecidedTap encountered in on_deactivate")
+                self.state = self.tapped_state();
+                self.tapping_after_release = false;
+                Ok(Some(Event::new(
+                    id,
+                    EventData::BehaviorEvent(BehaviorEvent::EndBehavior(self.tap)),
+                )))
+            }
+            HoldTapBehaviorState::DecidedHold if self.retro_tap && !self.interrupted => {
+                Ok(Some(self.tap_released(id, Some(self.hold))))
             }
-            HoldTapBehaviorState::DecidedHold => Some(Event::new(
-                self.id,
+            HoldTapBehaviorState::DecidedHold => Ok(Some(Event::new(
+                id,
                 EventData::BehaviorEvent(BehaviorEvent::EndBehavior(self.hold)),
-            )),
+            ))),
+            HoldTapBehaviorState::Tapping | HoldTapBehaviorState::Tapped => {
+                Err(Error::InvalidStateTransition)
+            }
             HoldTapBehaviorState::Pending => {
+                let held = self.hold_while_undecided.then_some(self.hold);
+                Ok(Some(self.tap_released(id, held)))
+            }
+        }
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.state {
+            HoldTapBehaviorState::Pending => Some(self.timeout.unwrap_or(config.hold_tap_timeout)),
+            HoldTapBehaviorState::Tapping => Some(self.tap_duration.unwrap_or(config.tap_duration)),
+            // The window starts at the release
+            HoldTapBehaviorState::Tapped if self.tapping_after_release => self
+                .quick_tap
+                .map(|q| q.saturating_sub(self.tap_duration.unwrap_or(config.tap_duration))),
+            HoldTapBehaviorState::Tapped => self.quick_tap,
+            _ => None,
+        }
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            HoldTapBehaviorState::Pending => match self.flavor {
+                HoldTapFlavor::TapUnlessInterrupted => Ok(self.decide_tap_held(id)),
+                _ => Ok(self.decide_hold(id)),
+            },
+            HoldTapBehaviorState::Tapping => {
+                self.state = HoldTapBehaviorState::Tapped;
+                Ok(Some(Event::new(
+                    id,
+                    EventData::BehaviorEvent(BehaviorEvent::EndBehavior(self.tap)),
+                )))
+            }
+            HoldTapBehaviorState::Tapped => {
+                // Quick-tap window is over, a new press starts from scratch
                 self.state = HoldTapBehaviorState::DecidedTap;
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
 
-                if self.hold_while_undecided {
-                    Some(Event::new(
-                        self.id,
-                        EventData::BehaviorEvent(BehaviorEvent::ReleaseTapBehavior(
-                            self.hold, self.tap,
-                        )),
-                    ))
-                } else {
-                    Some(Event::new(
-                        self.id,
-                        EventData::BehaviorEvent(BehaviorEvent::TapBehavior(self.tap)),
-                    ))
-                }
+    fn is_undecided(&self) -> bool {
+        self.state == HoldTapBehaviorState::Pending
+    }
+
+    fn is_done(&self) -> bool {
+        !matches!(
+            self.state,
+            HoldTapBehaviorState::Tapping | HoldTapBehaviorState::Tapped
+        )
+    }
+
+    fn is_listening(&self) -> bool {
+        // Any key pressed during the hold cancels the retro tap
+        self.retro_tap && self.state == HoldTapBehaviorState::DecidedHold
+    }
+
+    fn on_other_press(&mut self, id: BehaviorId, position: usize) -> Result<Option<Event>, Error> {
+        self.interrupted = true;
+        if self.state != HoldTapBehaviorState::Pending {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.interrupts.iter_mut().find(|p| p.is_none()) {
+            *slot = Some(position);
+        }
+
+        if self.hold_trigger_on_release {
+            return Ok(None);
+        }
+        if !self.is_hold_trigger(position) {
+            return Ok(self.decide_tap_held(id));
+        }
+
+        match self.flavor {
+            HoldTapFlavor::HoldPreferred | HoldTapFlavor::TapUnlessInterrupted => {
+                Ok(self.decide_hold(id))
+            }
+            HoldTapFlavor::Balanced | HoldTapFlavor::TapPreferred => Ok(None),
+        }
+    }
+
+    fn on_other_release(
+        &mut self,
+        id: BehaviorId,
+        position: usize,
+    ) -> Result<Option<Event>, Error> {
+        // Only keys pressed after this one count
+        if self.state != HoldTapBehaviorState::Pending || !self.interrupts.contains(&Some(position))
+        {
+            return Ok(None);
+        }
+
+        if !self.is_hold_trigger(position) {
+            return Ok(self.decide_tap_held(id));
+        }
+
+        match self.flavor {
+            HoldTapFlavor::Balanced => Ok(self.decide_hold(id)),
+            // Their decision on press was put off until now
+            HoldTapFlavor::HoldPreferred | HoldTapFlavor::TapUnlessInterrupted
+                if self.hold_trigger_on_release =>
+            {
+                Ok(self.decide_hold(id))
             }
+            _ => Ok(None),
         }
     }
+}
 
-    fn get_duration(&self) -> Option<Duration> {
-        Some(self.timeout)
+/// What a tap dance does when it ends on a given tap count
+#[derive(Debug, Clone, Copy)]
+pub struct TapDanceAction {
+    tap: SimpleBehavior,
+    hold: Option<SimpleBehavior>,
+}
+
+impl TapDanceAction {
+    /// Tapped if the dance ends with the key released, held until release otherwise
+    pub const fn new(tap: SimpleBehavior) -> Self {
+        Self { tap, hold: None }
+    }
+
+    /// Held instead of `tap` if the dance ends with the key still held
+    pub const fn with_hold(mut self, hold: SimpleBehavior) -> Self {
+        self.hold = Some(hold);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapDanceState {
+    /// Key is held, waiting for it to be released or for the tapping term to run out
+    Pressed,
+    /// Key was released, waiting for the next tap
+    Released,
+    /// Decided, the chosen behavior stays active until the key is released
+    Active,
+    /// Not dancing, the next press starts over
+    Idle,
+}
+
+/// Counts taps of its key, then runs the action for that count: `actions[0]` for a single tap,
+/// `actions[1]` for a double tap and so on. The dance ends when the tapping term runs out after a
+/// press or release, when another key is pressed, or right away on the last action's tap.
+#[derive(Debug, Clone)]
+pub struct TapDanceBehavior {
+    actions: &'static [TapDanceAction],
+    // Overrides `EngineConfig::tapping_term`
+    tapping_term: Option<Duration>,
+    state: TapDanceState,
+    count: usize,
+    active: Option<SimpleBehavior>,
+}
+
+impl TapDanceBehavior {
+    pub const fn new(actions: &'static [TapDanceAction]) -> Self {
+        Self {
+            actions,
+            tapping_term: None,
+            state: TapDanceState::Idle,
+            count: 0,
+            active: None,
+        }
     }
 
-    fn on_timeout(&mut self) -> Option<Event> {
+    pub const fn with_tapping_term(mut self, tapping_term: Duration) -> Self {
+        self.tapping_term = Some(tapping_term);
+        self
+    }
+
+    pub fn state(&self) -> TapDanceState {
+        self.state
+    }
+
+    fn action(&self) -> Option<&TapDanceAction> {
+        self.actions
+            .get(self.count.min(self.actions.len()).checked_sub(1)?)
+    }
+
+    /// End the dance with the key held, the chosen behavior stays active until release
+    fn activate(&mut self, id: BehaviorId, held: bool) -> Option<Event> {
+        self.state = TapDanceState::Active;
+        let action = self.action()?;
+        let behavior = match action.hold {
+            Some(hold) if held => hold,
+            _ => action.tap,
+        };
+
+        self.active = Some(behavior);
+        Some(Event::new(
+            id,
+            EventData::BehaviorEvent(BehaviorEvent::StartBehavior(behavior)),
+        ))
+    }
+
+    /// End the dance with the key released
+    fn tap(&mut self, id: BehaviorId) -> Option<Event> {
+        self.state = TapDanceState::Idle;
+        let action = self.action()?;
+        Some(Event::new(
+            id,
+            EventData::BehaviorEvent(BehaviorEvent::TapBehavior(action.tap, None)),
+        ))
+    }
+}
+
+impl BehaviorComplex for TapDanceBehavior {
+    fn on_press(&mut self, id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
         match self.state {
-            HoldTapBehaviorState::Pending => {
-                // Decide as hold
-                self.state = HoldTapBehaviorState::DecidedHold;
-                if self.hold_while_undecided {
-                    None
-                } else {
-                    Some(Event::new(
-                        self.id,
-                        EventData::BehaviorEvent(BehaviorEvent::StartBehavior(self.hold)),
+            TapDanceState::Idle => self.count = 1,
+            TapDanceState::Released => self.count += 1,
+            _ => return Err(Error::InvalidStateTransition),
+        }
+        self.state = TapDanceState::Pressed;
+
+        let last = self.action().is_some_and(|a| a.hold.is_none());
+        if self.count >= self.actions.len() && last {
+            // Nothing left to wait for
+            return Ok(self.activate(id, false));
+        }
+
+        Ok(None)
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            TapDanceState::Pressed if self.count >= self.actions.len() => Ok(self.tap(id)),
+            TapDanceState::Pressed => {
+                self.state = TapDanceState::Released;
+                Ok(None)
+            }
+            TapDanceState::Active => {
+                self.state = TapDanceState::Idle;
+                Ok(self.active.take().map(|b| {
+                    Event::new(id, EventData::BehaviorEvent(BehaviorEvent::EndBehavior(b)))
+                }))
+            }
+            _ => Err(Error::InvalidStateTransition),
+        }
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.state {
+            TapDanceState::Pressed | TapDanceState::Released => {
+                Some(self.tapping_term.unwrap_or(config.tapping_term))
+            }
+            _ => None,
+        }
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            TapDanceState::Pressed => Ok(self.activate(id, true)),
+            TapDanceState::Released => Ok(self.tap(id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_undecided(&self) -> bool {
+        matches!(self.state, TapDanceState::Pressed | TapDanceState::Released)
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == TapDanceState::Idle
+    }
+
+    fn on_other_press(&mut self, id: BehaviorId, _position: usize) -> Result<Option<Event>, Error> {
+        // Interrupted, the dance ends on the taps so far
+        match self.state {
+            TapDanceState::Pressed => Ok(self.activate(id, false)),
+            TapDanceState::Released => Ok(self.tap(id)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StickyKeyState {
+    Idle,
+    /// Key is held, the behavior is active
+    Held,
+    /// Tapped, the behavior stays active until the next key press is released
+    Latched,
+    /// Tapped twice, the behavior stays active until the key is pressed again
+    Locked,
+    /// Pressed to unlock, nothing left to do once it's released
+    Unlocking,
+}
+
+/// One-shot behavior, usually a modifier. Held together with other keys it works like the plain
+/// behavior. Tapped, it stays active for the next key pressed (modifier keys don't count) and is
+/// ended once that key is released, or after `release_after` if no key comes. Tapping it again
+/// within `EngineConfig::tapping_term` locks it until the next press, a later tap latches it again.
+#[derive(Debug, Clone)]
+pub struct StickyKeyBehavior {
+    behavior: SimpleBehavior,
+    release_after: Option<Duration>,
+    ignore_modifiers: bool,
+    state: StickyKeyState,
+    // Whether another key was pressed while held
+    interrupted: bool,
+    // Whether a tap would still lock it, until the tapping term after it was latched runs out
+    lockable: bool,
+    // The behavior that pressed the key this one is latched for
+    target: Option<BehaviorId>,
+}
+
+impl StickyKeyBehavior {
+    pub const fn new(behavior: SimpleBehavior) -> Self {
+        Self {
+            behavior,
+            release_after: None,
+            ignore_modifiers: true,
+            state: StickyKeyState::Idle,
+            interrupted: false,
+            lockable: false,
+            target: None,
+        }
+    }
+
+    /// End a latched behavior after `release_after` even if no key was pressed
+    pub const fn with_release_after(mut self, release_after: Duration) -> Self {
+        self.release_after = Some(release_after);
+        self
+    }
+
+    /// Whether modifier key presses are skipped when waiting for the next key, so several sticky
+    /// modifiers can be stacked. On by default.
+    pub const fn with_ignore_modifiers(mut self, ignore_modifiers: bool) -> Self {
+        self.ignore_modifiers = ignore_modifiers;
+        self
+    }
+
+    pub fn state(&self) -> StickyKeyState {
+        self.state
+    }
+
+    /// Timeout while latched. The lock window runs out first, then the rest of `release_after`.
+    fn latched_duration(
+        &self,
+        release_after: Option<Duration>,
+        config: &EngineConfig,
+    ) -> Option<Duration> {
+        if self.lockable {
+            Some(release_after.map_or(config.tapping_term, |r| r.min(config.tapping_term)))
+        } else {
+            release_after.map(|r| r.saturating_sub(config.tapping_term))
+        }
+    }
+
+    fn end(&mut self, id: BehaviorId) -> Option<Event> {
+        self.state = StickyKeyState::Idle;
+        self.target = None;
+        Some(Event::new(
+            id,
+            EventData::BehaviorEvent(BehaviorEvent::EndBehavior(self.behavior)),
+        ))
+    }
+}
+
+impl BehaviorComplex for StickyKeyBehavior {
+    fn on_press(&mut self, id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
+        match self.state {
+            StickyKeyState::Idle => {
+                self.state = StickyKeyState::Held;
+                self.interrupted = false;
+                Ok(Some(Event::new(
+                    id,
+                    EventData::BehaviorEvent(BehaviorEvent::StartBehavior(self.behavior)),
+                )))
+            }
+            StickyKeyState::Latched if self.lockable => {
+                self.state = StickyKeyState::Locked;
+                self.target = None;
+                Ok(None)
+            }
+            StickyKeyState::Latched => {
+                // Too late to lock, start over with the behavior still active
+                self.state = StickyKeyState::Held;
+                self.interrupted = false;
+                self.target = None;
+                Ok(None)
+            }
+            StickyKeyState::Locked => {
+                let event = self.end(id);
+                self.state = StickyKeyState::Unlocking;
+                Ok(event)
+            }
+            _ => Err(Error::InvalidStateTransition),
+        }
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            StickyKeyState::Held if self.interrupted => Ok(self.end(id)),
+            StickyKeyState::Held => {
+                self.state = StickyKeyState::Latched;
+                self.lockable = true;
+                Ok(None)
+            }
+            StickyKeyState::Locked => Ok(None),
+            StickyKeyState::Unlocking => {
+                self.state = StickyKeyState::Idle;
+                Ok(None)
+            }
+            _ => Err(Error::InvalidStateTransition),
+        }
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.state {
+            StickyKeyState::Latched => self.latched_duration(self.release_after, config),
+            _ => None,
+        }
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            StickyKeyState::Latched if self.lockable => {
+                self.lockable = false;
+                Ok(None)
+            }
+            // Only give up if the next key isn't held yet
+            StickyKeyState::Latched if self.target.is_none() => Ok(self.end(id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == StickyKeyState::Idle
+    }
+
+    fn watches_keys(&self) -> bool {
+        matches!(self.state, StickyKeyState::Held | StickyKeyState::Latched)
+    }
+
+    fn on_key_event(
+        &mut self,
+        id: BehaviorId,
+        event: &SimpleKeyEvent,
+        source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        match (self.state, event) {
+            (_, SimpleKeyEvent::Press(code)) if self.ignore_modifiers && code.key.is_modifier() => {
+                Ok(None)
+            }
+            (StickyKeyState::Held, SimpleKeyEvent::Press(_)) => {
+                self.interrupted = true;
+                Ok(None)
+            }
+            (StickyKeyState::Latched, SimpleKeyEvent::Press(_)) if self.target.is_none() => {
+                self.target = Some(source);
+                Ok(None)
+            }
+            (StickyKeyState::Latched, SimpleKeyEvent::Unpress(_))
+                if self.target == Some(source) =>
+            {
+                Ok(self.end(id))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Layer for just the next key. Held together with other keys it works like
+/// `MomentaryLayerBehavior`. Tapped, `layer` stays active until the next key pressed is
+/// released, or until the timeout runs out if there is none. Tapping it again within the tapping
+/// term locks the layer until the next press, like `StickyKeyBehavior`.
+#[derive(Debug, Clone)]
+pub struct OneShotLayerBehavior {
+    sticky: StickyKeyBehavior,
+    // Overrides `EngineConfig::one_shot_layer_timeout`
+    timeout: Option<Duration>,
+}
+
+impl OneShotLayerBehavior {
+    pub const fn new(layer: Layer) -> Self {
+        let layer = MomentaryLayerBehavior::new(layer);
+        Self {
+            // Any key on the layer uses it up, modifiers included
+            sticky: StickyKeyBehavior::new(SimpleBehavior::MomentaryLayer(layer))
+                .with_ignore_modifiers(false),
+            timeout: None,
+        }
+    }
+
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn state(&self) -> StickyKeyState {
+        self.sticky.state()
+    }
+}
+
+impl BehaviorComplex for OneShotLayerBehavior {
+    fn on_press(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<Option<Event>, Error> {
+        self.sticky.on_press(id, ctx)
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        self.sticky.on_unpress(id)
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.sticky.state() {
+            StickyKeyState::Latched => self.sticky.latched_duration(
+                Some(self.timeout.unwrap_or(config.one_shot_layer_timeout)),
+                config,
+            ),
+            _ => None,
+        }
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        self.sticky.on_timeout(id)
+    }
+
+    fn is_done(&self) -> bool {
+        self.sticky.is_done()
+    }
+
+    fn watches_keys(&self) -> bool {
+        self.sticky.watches_keys()
+    }
+
+    fn on_key_event(
+        &mut self,
+        id: BehaviorId,
+        event: &SimpleKeyEvent,
+        source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        self.sticky.on_key_event(id, event, source)
+    }
+}
+
+/// Keys that keep caps word on without being shifted, on top of letters
+pub const DEFAULT_CAPS_WORD_CONTINUE_KEYS: &[Key] = &[
+    Key::N1,
+    Key::N2,
+    Key::N3,
+    Key::N4,
+    Key::N5,
+    Key::N6,
+    Key::N7,
+    Key::N8,
+    Key::N9,
+    Key::N0,
+    Key::Backspace,
+    Key::Delete,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapsWordState {
+    Idle,
+    Active,
+    /// Pressed again while active, off once released
+    Stopping,
+}
+
+/// Shifts letters until the end of the word. Pressing any key that isn't a letter, a modifier or
+/// one of the continue keys turns it off, as do the idle timeout and pressing it again. Shift is
+/// only added to the shifted keys while they are held, a held shift is left alone.
+#[derive(Debug, Clone)]
+pub struct CapsWordBehavior {
+    continue_keys: &'static [Key],
+    shift_minus: bool,
+    // Overrides `EngineConfig::caps_word_timeout`
+    idle_timeout: Option<Duration>,
+    state: CapsWordState,
+}
+
+impl Default for CapsWordBehavior {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CapsWordBehavior {
+    pub const fn new() -> Self {
+        Self {
+            continue_keys: DEFAULT_CAPS_WORD_CONTINUE_KEYS,
+            shift_minus: false,
+            idle_timeout: None,
+            state: CapsWordState::Idle,
+        }
+    }
+
+    /// Replace `DEFAULT_CAPS_WORD_CONTINUE_KEYS`
+    pub const fn with_continue_keys(mut self, continue_keys: &'static [Key]) -> Self {
+        self.continue_keys = continue_keys;
+        self
+    }
+
+    /// Shift `Minus` as well, so `_` can join words
+    pub const fn with_shift_minus(mut self, shift_minus: bool) -> Self {
+        self.shift_minus = shift_minus;
+        self
+    }
+
+    pub const fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn state(&self) -> CapsWordState {
+        self.state
+    }
+
+    fn shifts(&self, key: Key) -> bool {
+        key.is_alpha() || (self.shift_minus && key == Key::Minus)
+    }
+
+    fn continues(&self, key: Key) -> bool {
+        self.shifts(key) || key.is_modifier() || self.continue_keys.contains(&key)
+    }
+}
+
+impl BehaviorComplex for CapsWordBehavior {
+    fn on_press(&mut self, _id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
+        self.state = match self.state {
+            CapsWordState::Idle => CapsWordState::Active,
+            CapsWordState::Active => CapsWordState::Stopping,
+            CapsWordState::Stopping => return Err(Error::InvalidStateTransition),
+        };
+        Ok(None)
+    }
+
+    fn on_unpress(&mut self, _id: BehaviorId) -> Result<Option<Event>, Error> {
+        if self.state == CapsWordState::Stopping {
+            self.state = CapsWordState::Idle;
+        }
+        Ok(None)
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.state {
+            CapsWordState::Active => Some(self.idle_timeout.unwrap_or(config.caps_word_timeout)),
+            _ => None,
+        }
+    }
+
+    fn on_timeout(&mut self, _id: BehaviorId) -> Result<Option<Event>, Error> {
+        if self.state == CapsWordState::Active {
+            self.state = CapsWordState::Idle;
+        }
+        Ok(None)
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == CapsWordState::Idle
+    }
+
+    fn watches_keys(&self) -> bool {
+        self.state == CapsWordState::Active
+    }
+
+    fn rearms_on_key_event(&self) -> bool {
+        // Stays on while typing
+        true
+    }
+
+    fn on_key_event(
+        &mut self,
+        _id: BehaviorId,
+        event: &SimpleKeyEvent,
+        _source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        if let SimpleKeyEvent::Press(code) = event
+            && !self.continues(code.key)
+        {
+            self.state = CapsWordState::Idle;
+        }
+        Ok(None)
+    }
+
+    fn extra_modifiers(&self, code: &KeyCode) -> Modifiers {
+        if self.state == CapsWordState::Active && self.shifts(code.key) {
+            Modifiers::LEFT_SHIFT
+        } else {
+            Modifiers::NONE
+        }
+    }
+}
+
+/// Presses the last key that was sent, with the modifiers it was sent with, for as long as it is
+/// held. Does nothing if no key was sent yet.
+#[derive(Debug, Clone, Default)]
+pub struct KeyRepeatBehavior {
+    // The key being repeated while held
+    key: Option<KeyCode>,
+}
+
+impl KeyRepeatBehavior {
+    pub const fn new() -> Self {
+        Self { key: None }
+    }
+}
+
+impl BehaviorComplex for KeyRepeatBehavior {
+    fn on_press(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<Option<Event>, Error> {
+        self.key = ctx.last_key;
+        Ok(self.key.map(|key| {
+            Event::new(
+                id,
+                EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Press(key))),
+            )
+        }))
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        Ok(self.key.take().map(|key| {
+            Event::new(
+                id,
+                EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Unpress(key))),
+            )
+        }))
+    }
+
+    fn get_duration(&self, _config: &EngineConfig) -> Option<Duration> {
+        None
+    }
+
+    fn on_timeout(&mut self, _id: BehaviorId) -> Result<Option<Event>, Error> {
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MacroStep {
+    Press(Key),
+    Release(Key),
+    /// Press and release, held for the macro's tap duration
+    Tap(Key),
+    /// Wait this long before the next step, instead of the macro's usual wait
+    Wait(Duration),
+    /// Tap a simple behavior, held for the macro's tap duration
+    Behavior(SimpleBehavior),
+    /// Wait for the macro's key to be released, so the steps before this one play on press and
+    /// the ones after it on release
+    PauseForRelease,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacroState {
+    Idle,
+    Playing,
+    /// Waiting at a `MacroStep::PauseForRelease`
+    Paused,
+}
+
+// How long the step that just played wants before the next one
+#[derive(Debug, Clone, Copy)]
+enum MacroDelay {
+    Step,
+    Tap,
+    Wait(Duration),
+}
+
+/// Plays `steps` in order. Each step waits for the previous one, plus the macro's wait, and taps
+/// wait for the key to be released. Playback carries on if the key is released before the end,
+/// pressing it again in the meantime does nothing.
+#[derive(Debug, Clone)]
+pub struct MacroBehavior {
+    steps: &'static [MacroStep],
+    // Override `EngineConfig::tap_duration` and `EngineConfig::macro_wait`
+    tap_duration: Option<Duration>,
+    wait: Option<Duration>,
+    state: MacroState,
+    // Index of the step to play next
+    next: usize,
+    delay: MacroDelay,
+    released: bool,
+}
+
+impl MacroBehavior {
+    pub const fn new(steps: &'static [MacroStep]) -> Self {
+        Self {
+            steps,
+            tap_duration: None,
+            wait: None,
+            state: MacroState::Idle,
+            next: 0,
+            delay: MacroDelay::Step,
+            released: false,
+        }
+    }
+
+    /// How long tapped keys and behaviors are held
+    pub const fn with_tap_duration(mut self, tap_duration: Duration) -> Self {
+        self.tap_duration = Some(tap_duration);
+        self
+    }
+
+    /// How long to wait between steps
+    pub const fn with_wait(mut self, wait: Duration) -> Self {
+        self.wait = Some(wait);
+        self
+    }
+
+    pub fn state(&self) -> MacroState {
+        self.state
+    }
+
+    /// Play steps until one has an event or has to be waited for
+    fn advance(&mut self, id: BehaviorId) -> Option<Event> {
+        while let Some(step) = self.steps.get(self.next) {
+            self.next += 1;
+            let data = match *step {
+                MacroStep::Press(key) => {
+                    self.delay = MacroDelay::Step;
+                    EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Press(key.into())))
+                }
+                MacroStep::Release(key) => {
+                    self.delay = MacroDelay::Step;
+                    EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Unpress(key.into())))
+                }
+                MacroStep::Tap(key) => {
+                    self.delay = MacroDelay::Tap;
+                    let behavior = SimpleBehavior::KeyPress(KeyPressBehavior::new(key));
+                    EventData::BehaviorEvent(BehaviorEvent::TapBehavior(
+                        behavior,
+                        self.tap_duration,
                     ))
                 }
+                MacroStep::Behavior(behavior) => {
+                    self.delay = MacroDelay::Tap;
+                    EventData::BehaviorEvent(BehaviorEvent::TapBehavior(
+                        behavior,
+                        self.tap_duration,
+                    ))
+                }
+                MacroStep::Wait(duration) => {
+                    self.delay = MacroDelay::Wait(duration);
+                    return None;
+                }
+                MacroStep::PauseForRelease if self.released => continue,
+                MacroStep::PauseForRelease => {
+                    self.state = MacroState::Paused;
+                    return None;
+                }
+            };
+            return Some(Event::new(id, data));
+        }
+
+        self.state = MacroState::Idle;
+        None
+    }
+}
+
+impl BehaviorComplex for MacroBehavior {
+    fn on_press(&mut self, id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
+        if self.state != MacroState::Idle {
+            return Ok(None);
+        }
+
+        self.state = MacroState::Playing;
+        self.next = 0;
+        self.released = false;
+        Ok(self.advance(id))
+    }
+
+    fn on_unpress(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        self.released = true;
+        if self.state == MacroState::Paused {
+            self.state = MacroState::Playing;
+            return Ok(self.advance(id));
+        }
+        Ok(None)
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        if self.state != MacroState::Playing {
+            return None;
+        }
+
+        let wait = self.wait.unwrap_or(config.macro_wait);
+        Some(match self.delay {
+            MacroDelay::Step => wait,
+            MacroDelay::Tap => self.tap_duration.unwrap_or(config.tap_duration) + wait,
+            MacroDelay::Wait(duration) => duration,
+        })
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        match self.state {
+            MacroState::Playing => Ok(self.advance(id)),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == MacroState::Idle
+    }
+}
+
+/// Longest key sequence a leader key can match
+pub const MAX_LEADER_KEYS: usize = 8;
+
+/// Typing `keys` after a leader key taps `behavior`
+#[derive(Debug, Clone)]
+pub struct LeaderSequence {
+    keys: &'static [Key],
+    behavior: ManualBehavior,
+}
+
+impl LeaderSequence {
+    pub const fn new(keys: &'static [Key], behavior: ManualBehavior) -> Self {
+        assert!(
+            !keys.is_empty() && keys.len() <= MAX_LEADER_KEYS,
+            "leader sequences need 1 to MAX_LEADER_KEYS keys"
+        );
+
+        Self { keys, behavior }
+    }
+
+    pub fn keys(&self) -> &'static [Key] {
+        self.keys
+    }
+
+    pub fn behavior(&self) -> &ManualBehavior {
+        &self.behavior
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderState {
+    Idle,
+    Capturing,
+}
+
+/// Captures the keys typed after it instead of sending them, until they spell one of the
+/// sequences, which is then tapped. Modifiers aren't captured and don't count as typed. Gives up
+/// once no sequence starts with the typed keys, or after the timeout with no key pressed. On
+/// timeout a sequence that was typed exactly but is also the start of a longer one still fires.
+///
+/// The sequences must be sorted by their keys (by usage code, shorter first on a tie) and unique,
+/// so they can be binary searched. `new` panics otherwise, build the behavior in a `const` or
+/// `static` to get that as a compile error instead.
+#[derive(Debug, Clone)]
+pub struct LeaderBehavior {
+    sequences: &'static [LeaderSequence],
+    // Overrides `EngineConfig::leader_timeout`
+    timeout: Option<Duration>,
+    state: LeaderState,
+    typed: [Key; MAX_LEADER_KEYS],
+    typed_len: usize,
+}
+
+impl LeaderBehavior {
+    pub const fn new(sequences: &'static [LeaderSequence]) -> Self {
+        let mut i = 1;
+        while i < sequences.len() {
+            assert!(
+                keys_before(sequences[i - 1].keys, sequences[i].keys),
+                "leader sequences must be sorted and unique"
+            );
+            i += 1;
+        }
+
+        Self {
+            sequences,
+            timeout: None,
+            state: LeaderState::Idle,
+            typed: [Key::A; MAX_LEADER_KEYS],
+            typed_len: 0,
+        }
+    }
+
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn state(&self) -> LeaderState {
+        self.state
+    }
+
+    /// The sequence the typed keys spell exactly, if any, and whether a longer sequence starts
+    /// with them
+    fn lookup(&self) -> (Option<&'static ManualBehavior>, bool) {
+        let typed = &self.typed[..self.typed_len];
+        let sequences = self.sequences;
+        let start = sequences.partition_point(|s| cmp_keys(s.keys, typed) == Ordering::Less);
+        let mut matching = sequences[start..]
+            .iter()
+            .take_while(|s| s.keys.starts_with(typed));
+
+        match matching.next() {
+            Some(s) if s.keys.len() == typed.len() => {
+                (Some(&s.behavior), matching.next().is_some())
             }
-            _ => None,
+            Some(_) => (None, true),
+            None => (None, false),
         }
     }
 
-    fn id(&self) -> usize {
-        self.id
+    /// Stop capturing, tapping `behavior` if there is one
+    fn finish(
+        &mut self,
+        id: BehaviorId,
+        behavior: Option<&'static ManualBehavior>,
+    ) -> Option<Event> {
+        self.state = LeaderState::Idle;
+        self.typed_len = 0;
+        behavior.map(|b| {
+            Event::new(
+                id,
+                EventData::BehaviorEvent(BehaviorEvent::TapManualBehavior(b)),
+            )
+        })
+    }
+}
+
+// Orders key sequences by usage code, then by length
+fn cmp_keys(lhs: &[Key], rhs: &[Key]) -> Ordering {
+    lhs.iter()
+        .map(|k| u8::from(*k))
+        .cmp(rhs.iter().map(|k| u8::from(*k)))
+}
+
+// `cmp_keys(lhs, rhs) == Ordering::Less`, for use in const contexts
+const fn keys_before(lhs: &[Key], rhs: &[Key]) -> bool {
+    let mut i = 0;
+    while i < lhs.len() && i < rhs.len() {
+        if lhs[i] as u8 != rhs[i] as u8 {
+            return (lhs[i] as u8) < (rhs[i] as u8);
+        }
+        i += 1;
     }
+    lhs.len() < rhs.len()
 }
 
-const BEHAVIOR_ID: Cell<usize> = Cell::new(1);
-pub fn get_behavior_id() -> usize {
-    let val = BEHAVIOR_ID.get();
-    BEHAVIOR_ID.set(val + 1);
-    val
+impl BehaviorComplex for LeaderBehavior {
+    fn on_press(&mut self, id: BehaviorId, _ctx: &PressContext) -> Result<Option<Event>, Error> {
+        match self.state {
+            LeaderState::Idle => {
+                self.state = LeaderState::Capturing;
+                self.typed_len = 0;
+                Ok(None)
+            }
+            // Pressed again before finishing a sequence
+            LeaderState::Capturing => Ok(self.finish(id, None)),
+        }
+    }
+
+    fn on_unpress(&mut self, _id: BehaviorId) -> Result<Option<Event>, Error> {
+        Ok(None)
+    }
+
+    fn get_duration(&self, config: &EngineConfig) -> Option<Duration> {
+        match self.state {
+            LeaderState::Capturing => Some(self.timeout.unwrap_or(config.leader_timeout)),
+            LeaderState::Idle => None,
+        }
+    }
+
+    fn on_timeout(&mut self, id: BehaviorId) -> Result<Option<Event>, Error> {
+        if self.state == LeaderState::Idle || self.typed_len == 0 {
+            return Ok(self.finish(id, None));
+        }
+
+        let (exact, _) = self.lookup();
+        Ok(self.finish(id, exact))
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == LeaderState::Idle
+    }
+
+    fn watches_keys(&self) -> bool {
+        self.state == LeaderState::Capturing
+    }
+
+    fn rearms_on_key_event(&self) -> bool {
+        // The timeout is per key of the sequence
+        true
+    }
+
+    fn captures_key(&self, code: &KeyCode) -> bool {
+        !code.key.is_modifier()
+    }
+
+    fn on_key_event(
+        &mut self,
+        id: BehaviorId,
+        event: &SimpleKeyEvent,
+        _source: BehaviorId,
+    ) -> Result<Option<Event>, Error> {
+        // Modifiers, e.g. from a hold-tap that decided on hold, go to the host and aren't part of
+        // any sequence
+        let SimpleKeyEvent::Press(code) = event else {
+            return Ok(None);
+        };
+        if code.key.is_modifier() {
+            return Ok(None);
+        }
+        if self.typed_len == MAX_LEADER_KEYS {
+            return Ok(self.finish(id, None));
+        }
+
+        self.typed[self.typed_len] = code.key;
+        self.typed_len += 1;
+        match self.lookup() {
+            (exact, false) => Ok(self.finish(id, exact)),
+            (_, true) => Ok(None),
+        }
+    }
 }
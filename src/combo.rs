@@ -0,0 +1,96 @@
+use crate::{behavior::ManualBehavior, config::EngineConfig, layer::Layer, timer::Duration};
+
+/// Most positions a single combo can have
+pub const MAX_COMBO_KEYS: usize = 8;
+/// How many combos can be held at the same time
+pub const MAX_ACTIVE_COMBOS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// When a combo's behavior is released
+pub enum ComboRelease {
+    /// As soon as any of its keys is released
+    #[default]
+    AnyReleased,
+    /// Once all of its keys are released
+    AllReleased,
+}
+
+/// Pressing all of `positions` within the combo timeout of each other presses `behavior` instead
+/// of the behaviors bound to those positions. If several combos match, the one with the most
+/// positions wins.
+#[derive(Debug, Clone)]
+pub struct Combo {
+    positions: &'static [usize],
+    behavior: ManualBehavior,
+    // Overrides `EngineConfig::combo_timeout`
+    timeout: Option<Duration>,
+    layers: Option<&'static [Layer]>,
+    release: ComboRelease,
+}
+
+impl Combo {
+    pub const fn new(positions: &'static [usize], behavior: ManualBehavior) -> Self {
+        assert!(
+            !positions.is_empty() && positions.len() <= MAX_COMBO_KEYS,
+            "combos need 1 to MAX_COMBO_KEYS positions"
+        );
+
+        Self {
+            positions,
+            behavior,
+            timeout: None,
+            layers: None,
+            release: ComboRelease::AnyReleased,
+        }
+    }
+
+    /// How long after the first of its keys the last one has to be pressed
+    pub const fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Only match while the top active layer is one of `layers`
+    pub const fn with_layers(mut self, layers: &'static [Layer]) -> Self {
+        self.layers = Some(layers);
+        self
+    }
+
+    pub const fn with_release(mut self, release: ComboRelease) -> Self {
+        self.release = release;
+        self
+    }
+
+    pub fn positions(&self) -> &'static [usize] {
+        self.positions
+    }
+
+    pub fn behavior(&self) -> &ManualBehavior {
+        &self.behavior
+    }
+
+    pub fn release(&self) -> ComboRelease {
+        self.release
+    }
+
+    pub fn timeout(&self, config: &EngineConfig) -> Duration {
+        self.timeout.unwrap_or(config.combo_timeout)
+    }
+
+    pub fn is_active_on(&self, layer: Layer) -> bool {
+        self.layers.is_none_or(|l| l.contains(&layer))
+    }
+
+    pub fn contains(&self, position: usize) -> bool {
+        self.positions.contains(&position)
+    }
+}
+
+/// A combo whose behavior was pressed, with the combo's keys that are still held
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ActiveCombo {
+    pub index: usize,
+    // Bit `i` is set while `positions[i]` is held
+    pub held: u8,
+    pub released: bool,
+}
@@ -0,0 +1,45 @@
+use crate::timer::Duration;
+
+/// Global timing settings for the engine. Can be changed at runtime through
+/// `State::config_mut`, behaviors that have their own settings override these per instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// How long a synthetic tap holds its key down before releasing it. Some hosts (RDP sessions,
+    /// certain KVMs) miss taps that are too short.
+    pub tap_duration: Duration,
+    /// How long a hold-tap waits before deciding on hold
+    pub hold_tap_timeout: Duration,
+    /// How long a tap dance waits for the next tap, or for the key to be released
+    pub tapping_term: Duration,
+    /// How long after the first key of a combo the others have to be pressed
+    pub combo_timeout: Duration,
+    /// How long a tapped one-shot layer stays active if no key is pressed on it
+    pub one_shot_layer_timeout: Duration,
+    /// How long caps word stays on while nothing is typed
+    pub caps_word_timeout: Duration,
+    /// How long a macro waits between steps
+    pub macro_wait: Duration,
+    /// How long a leader key waits for each key of a sequence
+    pub leader_timeout: Duration,
+}
+
+impl EngineConfig {
+    pub const fn new() -> Self {
+        Self {
+            tap_duration: Duration::from_millis(100),
+            hold_tap_timeout: Duration::from_millis(200),
+            tapping_term: Duration::from_millis(200),
+            combo_timeout: Duration::from_millis(50),
+            one_shot_layer_timeout: Duration::from_millis(1000),
+            caps_word_timeout: Duration::from_millis(5000),
+            macro_wait: Duration::from_millis(10),
+            leader_timeout: Duration::from_millis(1000),
+        }
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
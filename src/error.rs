@@ -0,0 +1,63 @@
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The event queue (`MAX_EVENTS`) is full
+    EventQueueFull,
+    /// The timer event queue (`MAX_TIMER_EVENTS`) is full
+    TimerQueueFull,
+    /// More than `MAX_HELD_KEYS` keys would be held
+    TooManyHeldKeys,
+    /// More than `MAX_HELD_BEHAVIORS` behaviors would be held
+    TooManyHeldBehaviors,
+    /// More than `MAX_ACTIVE_LAYERS` layers would be active
+    LayerStackFull,
+    /// More than `MAX_BUFFERED_EVENTS` key events arrived while a behavior was undecided
+    TooManyBufferedEvents,
+    /// More than `MAX_ACTIVE_COMBOS` combos would be held
+    TooManyActiveCombos,
+    /// The host fell more than `MAX_PENDING_REPORTS` reports behind
+    ReportQueueFull,
+    /// A key event for a position past the keymap's
+    InvalidPosition,
+    /// An event referred to a behavior that doesn't exist (anymore)
+    UnknownBehavior,
+    /// A behavior was asked to do something its current state doesn't allow
+    InvalidStateTransition,
+}
+
+impl Error {
+    /// Whether this error means one of the fixed-size buffers ran out of space
+    pub fn is_capacity(&self) -> bool {
+        matches!(
+            self,
+            Error::EventQueueFull
+                | Error::TimerQueueFull
+                | Error::TooManyHeldKeys
+                | Error::TooManyHeldBehaviors
+                | Error::LayerStackFull
+                | Error::TooManyBufferedEvents
+                | Error::TooManyActiveCombos
+                | Error::ReportQueueFull
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Error::EventQueueFull => "event queue is full",
+            Error::TimerQueueFull => "timer event queue is full",
+            Error::TooManyHeldKeys => "too many held keys",
+            Error::TooManyHeldBehaviors => "too many held behaviors",
+            Error::LayerStackFull => "layer stack is full",
+            Error::TooManyBufferedEvents => "too many key events buffered",
+            Error::TooManyActiveCombos => "too many active combos",
+            Error::ReportQueueFull => "report queue is full",
+            Error::InvalidPosition => "key position out of range",
+            Error::UnknownBehavior => "unknown behavior",
+            Error::InvalidStateTransition => "invalid behavior state transition",
+        };
+        f.write_str(msg)
+    }
+}
@@ -1,14 +1,26 @@
-use core::cmp::Ordering;
+use crate::{
+    behavior::{ManualBehavior, SimpleBehavior},
+    layer::Layer,
+    registry::BehaviorId,
+    timer::{Duration, Instant},
+    vboard::KeyCode,
+};
 
-use crate::{behavior::SimpleBehavior, layer::Layer, timer::Instant, vboard::Key};
+/// A physical key press or release, as passed to `State::key_down`/`State::key_up`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionEvent {
+    pub position: usize,
+    pub pressed: bool,
+    pub instant: Instant,
+}
 
 pub struct Event {
-    pub behavior_id: usize,
+    pub behavior_id: BehaviorId,
     pub data: EventData,
 }
 
 impl Event {
-    pub fn new(behavior_id: usize, data: EventData) -> Self {
+    pub fn new(behavior_id: BehaviorId, data: EventData) -> Self {
         Self { behavior_id, data }
     }
 }
@@ -19,12 +31,13 @@ pub enum EventData {
     LayerEvent(LayerEvent),
 }
 pub enum ComplexKeyEvent {
-    ReleaseTap(Key, Key),
-    ReleasePress(Key, Key),
+    ReleaseTap(KeyCode, KeyCode),
+    ReleasePress(KeyCode, KeyCode),
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SimpleKeyEvent {
-    Press(Key),
-    Unpress(Key),
+    Press(KeyCode),
+    Unpress(KeyCode),
 }
 
 pub enum KeyEvent {
@@ -35,12 +48,20 @@ pub enum KeyEvent {
 pub enum LayerEvent {
     AddLayer(Layer),
     RemoveToLayer(Layer),
+    RemoveLayer(Layer),
 }
 
+/// The `Option<Duration>` on taps overrides how long the tapped behavior is held, `None` uses
+/// `EngineConfig::tap_duration`
 pub enum BehaviorEvent {
     StartBehavior(SimpleBehavior),
     EndBehavior(SimpleBehavior),
-    TapBehavior(SimpleBehavior), // Taps are performed by main processing loop, no need to
-    ReleasePressBehavior(SimpleBehavior, SimpleBehavior), // Release the first and press the second
-    ReleaseTapBehavior(SimpleBehavior, SimpleBehavior),   // Release the first and tap the second
+    /// Taps are performed by the main processing loop, the behavior doesn't need to end them
+    TapBehavior(SimpleBehavior, Option<Duration>),
+    /// Release the first and press the second
+    ReleasePressBehavior(SimpleBehavior, SimpleBehavior),
+    /// Release the first and tap the second
+    ReleaseTapBehavior(SimpleBehavior, SimpleBehavior, Option<Duration>),
+    /// Tap a copy of the behavior, which then runs on its own
+    TapManualBehavior(&'static ManualBehavior),
 }
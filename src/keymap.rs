@@ -0,0 +1,118 @@
+use crate::{
+    behavior::ManualBehavior,
+    layer::{Layer, LayerStack},
+};
+
+#[derive(Debug, Clone)]
+/// What a single keymap slot does when its position is pressed on that layer
+pub enum Binding {
+    Behavior(ManualBehavior),
+    /// Fall through to the next active layer below this one
+    Transparent,
+    /// Do nothing, and don't fall through either
+    NoOp,
+}
+
+/// Maps physical key positions to behaviors, with one row of bindings per layer. Storage is a
+/// fixed-size `LAYERS` x `POSITIONS` grid so it can live in a `static`.
+#[derive(Debug, Clone)]
+pub struct Keymap<const LAYERS: usize, const POSITIONS: usize> {
+    layers: [[Binding; POSITIONS]; LAYERS],
+}
+
+impl<const LAYERS: usize, const POSITIONS: usize> Keymap<LAYERS, POSITIONS> {
+    pub const fn new(layers: [[Binding; POSITIONS]; LAYERS]) -> Self {
+        Self { layers }
+    }
+
+    pub fn get(&self, layer: Layer, position: usize) -> Option<&Binding> {
+        self.layers.get(layer.index())?.get(position)
+    }
+
+    pub fn set(&mut self, layer: Layer, position: usize, binding: Binding) {
+        if let Some(slot) = self
+            .layers
+            .get_mut(layer.index())
+            .and_then(|l| l.get_mut(position))
+        {
+            *slot = binding;
+        }
+    }
+
+    /// Find the behavior bound to `position`, walking the active layers from the top of the stack
+    /// down. `Transparent` slots (and layers this keymap doesn't define) fall through to the next
+    /// layer, a `NoOp` slot stops the search. Also returns the layer the behavior was found on.
+    pub fn resolve(
+        &self,
+        layers: &LayerStack,
+        position: usize,
+    ) -> Option<(Layer, &ManualBehavior)> {
+        for layer in layers.iter() {
+            match self.get(*layer, position) {
+                Some(Binding::Behavior(b)) => return Some((*layer, b)),
+                Some(Binding::NoOp) => return None,
+                Some(Binding::Transparent) | None => continue,
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{KeyPressBehavior, SimpleBehavior},
+        vboard::Key,
+    };
+
+    const fn kp(key: Key) -> Binding {
+        Binding::Behavior(ManualBehavior::Simple(SimpleBehavior::KeyPress(
+            KeyPressBehavior::new(key),
+        )))
+    }
+
+    static KEYMAP: Keymap<3, 3> = Keymap::new([
+        [kp(Key::A), kp(Key::B), kp(Key::C)],
+        [Binding::Transparent, kp(Key::N1), Binding::NoOp],
+        [Binding::Transparent, Binding::Transparent, kp(Key::N3)],
+    ]);
+
+    fn resolved_layer(layers: &LayerStack, position: usize) -> Option<usize> {
+        KEYMAP
+            .resolve(layers, position)
+            .map(|(layer, _)| layer.index())
+    }
+
+    #[test]
+    fn transparent_falls_through() {
+        let mut layers = LayerStack::new();
+        layers.push(Layer::new(1)).unwrap();
+        layers.push(Layer::new(2)).unwrap();
+
+        // Transparent on both upper layers ends up on the base layer
+        assert_eq!(resolved_layer(&layers, 0), Some(0));
+        // Transparent on the top layer only
+        assert_eq!(resolved_layer(&layers, 1), Some(1));
+        assert_eq!(resolved_layer(&layers, 2), Some(2));
+    }
+
+    #[test]
+    fn no_op_stops_the_search() {
+        let mut layers = LayerStack::new();
+        layers.push(Layer::new(1)).unwrap();
+
+        assert_eq!(resolved_layer(&layers, 2), None);
+    }
+
+    #[test]
+    fn undefined_layers_and_positions() {
+        let mut layers = LayerStack::new();
+        layers.push(Layer::new(5)).unwrap();
+
+        // Layers the keymap doesn't define are transparent
+        assert_eq!(resolved_layer(&layers, 1), Some(0));
+        assert_eq!(resolved_layer(&layers, 3), None);
+    }
+}
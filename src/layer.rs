@@ -1,2 +1,174 @@
-#[derive(Debug, Clone, Copy)]
-pub struct Layer {}
+pub const MAX_ACTIVE_LAYERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Layer {
+    index: usize,
+}
+
+impl Layer {
+    pub const fn new(index: usize) -> Self {
+        Self { index }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// Ordered stack of active layers. The base layer (index 0) always sits at the bottom and is
+/// never removed, the most recently added layer is on top.
+#[derive(Debug)]
+pub struct LayerStack {
+    layers: [Layer; MAX_ACTIVE_LAYERS],
+    len: usize,
+}
+
+impl Default for LayerStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LayerStack {
+    pub const fn new() -> Self {
+        Self {
+            layers: [Layer::new(0); MAX_ACTIVE_LAYERS],
+            len: 1,
+        }
+    }
+
+    /// Push a layer on top of the stack. Fails if the stack is full, in which case the stack is
+    /// left unchanged.
+    pub fn push(&mut self, layer: Layer) -> Result<(), Layer> {
+        if self.len == MAX_ACTIVE_LAYERS {
+            return Err(layer);
+        }
+
+        self.layers[self.len] = layer;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Pop the stack down until `layer` is on top. If `layer` appears more than once the topmost
+    /// occurrence is used. If `layer` is not active this does nothing.
+    pub fn remove_to(&mut self, layer: Layer) {
+        if let Some(pos) = self.layers[..self.len].iter().rposition(|l| *l == layer) {
+            self.len = pos + 1;
+        }
+    }
+
+    /// Remove the topmost occurrence of `layer`, leaving every layer above and below it in place.
+    /// The base layer and inactive layers are left alone.
+    ///
+    /// This is what makes out-of-order releases of momentary layers well defined: with
+    /// `MO(0 -> 1)` and `MO(0 -> 2)` held, releasing the first leaves `[0, 2]`, so layer 2 stays
+    /// active for as long as its key is held.
+    pub fn remove(&mut self, layer: Layer) {
+        if let Some(pos) = self.layers[1..self.len].iter().rposition(|l| *l == layer) {
+            self.layers.copy_within(pos + 2..self.len, pos + 1);
+            self.len -= 1;
+        }
+    }
+
+    /// Drop everything except the base layer
+    pub fn reset(&mut self) {
+        self.len = 1;
+    }
+
+    pub fn top(&self) -> Layer {
+        self.layers[self.len - 1]
+    }
+
+    pub fn contains(&self, layer: Layer) -> bool {
+        self.layers[..self.len].contains(&layer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Always false in practice, the base layer can't be removed
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the active layers, from the top of the stack down to the base layer
+    pub fn iter(&self) -> impl Iterator<Item = &Layer> {
+        self.layers[..self.len].iter().rev()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(stack: &LayerStack) -> [usize; MAX_ACTIVE_LAYERS] {
+        let mut res = [usize::MAX; MAX_ACTIVE_LAYERS];
+        for (slot, layer) in res.iter_mut().zip(stack.iter()) {
+            *slot = layer.index();
+        }
+        res
+    }
+
+    #[test]
+    fn remove_keeps_other_layers() {
+        let mut stack = LayerStack::new();
+        stack.push(Layer::new(1)).unwrap();
+        stack.push(Layer::new(2)).unwrap();
+
+        stack.remove(Layer::new(1));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.top(), Layer::new(2));
+        assert!(!stack.contains(Layer::new(1)));
+
+        stack.remove(Layer::new(2));
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.top(), Layer::new(0));
+    }
+
+    #[test]
+    fn remove_takes_topmost_occurrence() {
+        let mut stack = LayerStack::new();
+        stack.push(Layer::new(1)).unwrap();
+        stack.push(Layer::new(2)).unwrap();
+        stack.push(Layer::new(1)).unwrap();
+
+        stack.remove(Layer::new(1));
+        assert_eq!(indices(&stack)[..3], [2, 1, 0]);
+    }
+
+    #[test]
+    fn remove_ignores_base_and_inactive_layers() {
+        let mut stack = LayerStack::new();
+        stack.push(Layer::new(1)).unwrap();
+
+        stack.remove(Layer::new(0));
+        stack.remove(Layer::new(3));
+        assert_eq!(indices(&stack)[..2], [1, 0]);
+    }
+
+    #[test]
+    fn remove_to_unwinds() {
+        let mut stack = LayerStack::new();
+        stack.push(Layer::new(1)).unwrap();
+        stack.push(Layer::new(2)).unwrap();
+        stack.push(Layer::new(3)).unwrap();
+
+        stack.remove_to(Layer::new(1));
+        assert_eq!(indices(&stack)[..2], [1, 0]);
+
+        stack.remove_to(Layer::new(2));
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn push_fails_when_full() {
+        let mut stack = LayerStack::new();
+        for index in 1..MAX_ACTIVE_LAYERS {
+            stack.push(Layer::new(index)).unwrap();
+        }
+
+        assert_eq!(stack.push(Layer::new(1)), Err(Layer::new(1)));
+        assert_eq!(stack.len(), MAX_ACTIVE_LAYERS);
+    }
+}
@@ -1,13 +1,919 @@
 #![no_std]
 
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
 pub mod behavior;
+pub mod combo;
+pub mod config;
+pub mod error;
 pub mod event;
+pub mod keymap;
 pub mod layer;
+pub mod registry;
+pub mod report;
+#[cfg(any(test, feature = "std"))]
+pub mod sim;
 pub mod state;
 pub mod timer;
 pub mod vboard;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use crate::{
+        behavior::{KeyPressBehavior, ManualBehavior, SimpleBehavior},
+        config::EngineConfig,
+        keymap::{Binding, Keymap},
+        report::KeyboardReport,
+        sim::Simulator,
+        timer::{Duration, Instant},
+        vboard::{Key, KeyCode, Modifiers},
+    };
+
+    /// How long a key is held when the test doesn't care
+    const TAP: Duration = Duration::from_millis(20);
+
+    const fn kp(key: Key) -> SimpleBehavior {
+        SimpleBehavior::KeyPress(KeyPressBehavior::new(key))
+    }
+
+    /// Binding that just presses `key`
+    const fn key(key: Key) -> Binding {
+        Binding::Behavior(ManualBehavior::Simple(kp(key)))
+    }
+
+    const fn ms(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    /// Simulator for `layers` with the default config
+    fn simulator<const LAYERS: usize, const POSITIONS: usize>(
+        layers: [[Binding; POSITIONS]; LAYERS],
+    ) -> Simulator<LAYERS, POSITIONS> {
+        Simulator::new(Keymap::new(layers), EngineConfig::new())
+    }
+
+    /// The instants at which `key` went down and came back up in the recorded reports
+    fn key_changes(reports: &[(Instant, KeyboardReport)], key: Key) -> std::vec::Vec<Instant> {
+        let mut pressed = false;
+        reports
+            .iter()
+            .filter(|(_, r)| {
+                let changed = r.is_pressed(key) != pressed;
+                pressed = r.is_pressed(key);
+                changed
+            })
+            .map(|(i, _)| *i)
+            .collect()
+    }
+
+    #[test]
+    fn modifier_wrapped_key_keeps_held_modifier() {
+        let bang = KeyCode::new(Key::N1).with_modifiers(Modifiers::LEFT_SHIFT);
+        let mut sim = simulator([[
+            Binding::Behavior(ManualBehavior::Simple(SimpleBehavior::KeyPress(
+                KeyPressBehavior::with_code(bang),
+            ))),
+            key(Key::LeftShift),
+        ]]);
+
+        sim.tap(0, ms(0), TAP).unwrap();
+        sim.press(1, ms(50)).unwrap();
+        sim.tap(0, ms(100), TAP).unwrap();
+        sim.release(1, ms(200)).unwrap();
+
+        assert_eq!(
+            key_changes(sim.reports(), Key::N1),
+            [ms(0), ms(20), ms(100), ms(120)]
+        );
+        assert_eq!(
+            key_changes(sim.reports(), Key::LeftShift),
+            [ms(0), ms(20), ms(50), ms(200)]
+        );
+    }
+
+    mod layers {
+        use super::*;
+        use crate::{behavior::MomentaryLayerBehavior, layer::Layer};
+
+        #[test]
+        fn release_goes_to_the_layer_of_the_press() {
+            let mo = SimpleBehavior::MomentaryLayer(MomentaryLayerBehavior::new(Layer::new(1)));
+            let mut sim = simulator([
+                [key(Key::A), Binding::Behavior(ManualBehavior::Simple(mo))],
+                [key(Key::B), Binding::Transparent],
+            ]);
+
+            sim.press(1, ms(0)).unwrap();
+            sim.press(0, ms(100)).unwrap();
+            // Back on layer 0 while B is still held
+            sim.release(1, ms(200)).unwrap();
+            sim.release(0, ms(300)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(100), ms(300)]);
+            assert!(key_changes(sim.reports(), Key::A).is_empty());
+        }
+    }
+
+    mod hold_tap {
+        use super::*;
+        use crate::behavior::{HoldTapBehavior, HoldTapFlavor};
+
+        /// Shift on hold, A on tap, with the default 200ms timeout
+        fn shift_a() -> HoldTapBehavior {
+            HoldTapBehavior::new(kp(Key::LeftShift), kp(Key::A))
+        }
+
+        /// Position 0 is `ht`, position 1 is B
+        fn ht_sim(ht: HoldTapBehavior) -> Simulator<1, 2> {
+            simulator([[Binding::Behavior(ManualBehavior::HoldTap(ht)), key(Key::B)]])
+        }
+
+        fn flavored_sim(flavor: HoldTapFlavor) -> Simulator<1, 2> {
+            ht_sim(shift_a().with_flavor(flavor))
+        }
+
+        #[test]
+        fn tap() {
+            let mut sim = ht_sim(shift_a());
+            sim.tap(0, ms(0), Duration::from_millis(50)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(50), ms(150)]);
+            assert!(key_changes(sim.reports(), Key::LeftShift).is_empty());
+        }
+
+        #[test]
+        fn hold() {
+            let mut sim = ht_sim(shift_a());
+            sim.tap(0, ms(0), Duration::from_millis(300)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(200), ms(300)]
+            );
+            assert!(key_changes(sim.reports(), Key::A).is_empty());
+        }
+
+        #[test]
+        fn custom_timeout() {
+            let mut sim = ht_sim(shift_a().with_timeout(Duration::from_millis(100)));
+            sim.tap(0, ms(0), Duration::from_millis(150)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(100), ms(150)]
+            );
+        }
+
+        #[test]
+        fn quick_tap_repeats_tap() {
+            let mut sim = ht_sim(shift_a().with_quick_tap(Duration::from_millis(300)));
+            sim.tap(0, ms(0), Duration::from_millis(50)).unwrap();
+            // Within the window, held past the hold-tap timeout
+            sim.tap(0, ms(200), Duration::from_millis(300)).unwrap();
+            // Window is over, back to a normal hold-tap
+            sim.tap(0, ms(1000), Duration::from_millis(300)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(50), ms(150), ms(200), ms(500)]
+            );
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(1200), ms(1300)]
+            );
+        }
+
+        #[test]
+        fn quick_tap_window_starts_at_release() {
+            let mut sim = ht_sim(shift_a().with_quick_tap(Duration::from_millis(150)));
+            sim.tap(0, ms(0), Duration::from_millis(50)).unwrap();
+            // The tap lasts until 150, but the window closed at 200
+            sim.tap(0, ms(250), Duration::from_millis(300)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(50), ms(150)]);
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(450), ms(550)]
+            );
+        }
+
+        #[test]
+        fn quick_tap_fast_double_tap() {
+            let ht = shift_a().with_quick_tap(Duration::from_millis(300));
+            let mut sim = ht_sim(ht.clone());
+            sim.tap(0, ms(0), Duration::from_millis(10)).unwrap();
+            // The first tap is still active, it ends right before the second one starts
+            sim.tap(0, ms(40), Duration::from_millis(10)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(10), ms(40), ms(40), ms(50)]
+            );
+            assert!(key_changes(sim.reports(), Key::LeftShift).is_empty());
+
+            // Held, the second tap isn't cut short where the first one would have ended
+            let mut sim = ht_sim(ht);
+            sim.tap(0, ms(0), Duration::from_millis(10)).unwrap();
+            sim.tap(0, ms(40), Duration::from_millis(300)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(10), ms(40), ms(40), ms(340)]
+            );
+        }
+
+        #[test]
+        fn require_prior_idle_taps_while_typing() {
+            let mut sim = ht_sim(shift_a().with_require_prior_idle(Duration::from_millis(150)));
+            sim.tap(1, ms(0), TAP).unwrap();
+            sim.tap(0, ms(100), Duration::from_millis(300)).unwrap();
+            sim.tap(0, ms(1000), Duration::from_millis(300)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(100), ms(400)]);
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(1200), ms(1300)]
+            );
+        }
+
+        #[test]
+        fn retro_tap_unless_interrupted() {
+            let mut sim = ht_sim(shift_a().with_retro_tap(true));
+            sim.tap(0, ms(0), Duration::from_millis(300)).unwrap();
+            sim.press(0, ms(1000)).unwrap();
+            sim.tap(1, ms(1250), TAP).unwrap();
+            sim.release(0, ms(1400)).unwrap();
+            sim.run_until(ms(2000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(200), ms(300), ms(1200), ms(1400)]
+            );
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(300), ms(400)]);
+        }
+
+        #[test]
+        fn hold_preferred_holds_on_interrupt() {
+            let mut sim = flavored_sim(HoldTapFlavor::HoldPreferred);
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(1, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(50), ms(100)]
+            );
+            assert!(key_changes(sim.reports(), Key::A).is_empty());
+        }
+
+        #[test]
+        fn balanced_needs_interrupting_release() {
+            // Interrupting key still held when the hold-tap is released: tap
+            let mut sim = flavored_sim(HoldTapFlavor::Balanced);
+            sim.press(0, ms(0)).unwrap();
+            sim.press(1, ms(50)).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            sim.release(1, ms(150)).unwrap();
+            assert!(key_changes(sim.reports(), Key::LeftShift).is_empty());
+
+            // Interrupting key tapped inside the hold-tap: hold
+            let mut sim = flavored_sim(HoldTapFlavor::Balanced);
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(1, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(70), ms(100)]
+            );
+        }
+
+        #[test]
+        fn tap_unless_interrupted_taps_on_timeout() {
+            let mut sim = flavored_sim(HoldTapFlavor::TapUnlessInterrupted);
+            sim.tap(0, ms(0), Duration::from_millis(300)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(200), ms(300)]);
+            assert!(key_changes(sim.reports(), Key::LeftShift).is_empty());
+        }
+
+        #[test]
+        fn interrupting_key_waits_for_hold() {
+            let mut sim = flavored_sim(HoldTapFlavor::Balanced);
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(1, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+
+            // Held back until the release decides the hold, then reported after shift
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(70), ms(70)]);
+            assert!(
+                sim.reports()
+                    .iter()
+                    .filter(|(_, r)| r.is_pressed(Key::B))
+                    .all(|(_, r)| r.is_pressed(Key::LeftShift))
+            );
+        }
+
+        #[test]
+        fn interrupting_key_waits_for_tap() {
+            let mut sim = ht_sim(shift_a());
+            sim.press(0, ms(0)).unwrap();
+            sim.press(1, ms(50)).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            sim.release(1, ms(150)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(100), ms(200)]);
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(100), ms(150)]);
+            let first_b = sim.reports().iter().find(|(_, r)| r.is_pressed(Key::B));
+            assert!(first_b.is_some_and(|(_, r)| r.is_pressed(Key::A)));
+        }
+
+        #[test]
+        fn hold_trigger_positions() {
+            // Positions 0 and 1 on one hand, 2 on the other
+            let positional_sim = || {
+                let ht = shift_a()
+                    .with_flavor(HoldTapFlavor::HoldPreferred)
+                    .with_hold_trigger_positions(&[2]);
+                simulator([[
+                    Binding::Behavior(ManualBehavior::HoldTap(ht)),
+                    key(Key::B),
+                    key(Key::C),
+                ]])
+            };
+
+            let mut sim = positional_sim();
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(1, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(50), ms(100)]);
+            assert!(key_changes(sim.reports(), Key::LeftShift).is_empty());
+
+            let mut sim = positional_sim();
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(2, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(50), ms(100)]
+            );
+            assert!(key_changes(sim.reports(), Key::A).is_empty());
+        }
+
+        #[test]
+        fn hold_while_undecided_switches_to_tap() {
+            let mut sim = ht_sim(
+                shift_a()
+                    .with_flavor(HoldTapFlavor::TapUnlessInterrupted)
+                    .with_hold_while_undecided(true),
+            );
+            sim.tap(0, ms(0), Duration::from_millis(300)).unwrap();
+
+            // Deciding on tap releases the hold and presses the tap (`ReleasePressBehavior`)
+            assert_eq!(key_changes(sim.reports(), Key::LeftShift), [ms(0), ms(200)]);
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(200), ms(300)]);
+        }
+    }
+
+    mod tap_dance {
+        use super::*;
+        use crate::behavior::{TapDanceAction, TapDanceBehavior};
+
+        #[test]
+        fn tap_dance() {
+            static ACTIONS: [TapDanceAction; 2] = [
+                TapDanceAction::new(kp(Key::A)),
+                TapDanceAction::new(kp(Key::B)).with_hold(kp(Key::LeftShift)),
+            ];
+            let mut sim = simulator([[
+                Binding::Behavior(ManualBehavior::TapDance(TapDanceBehavior::new(&ACTIONS))),
+                key(Key::C),
+            ]]);
+            let tap = Duration::from_millis(50);
+
+            // Single tap, decided once the tapping term runs out
+            sim.tap(0, ms(0), tap).unwrap();
+            // Double tap. The last action has a hold, so it is decided on release rather than on
+            // press
+            sim.tap(0, ms(1000), tap).unwrap();
+            sim.tap(0, ms(1100), tap).unwrap();
+            // Tap then hold
+            sim.tap(0, ms(2000), tap).unwrap();
+            sim.tap(0, ms(2100), Duration::from_millis(400)).unwrap();
+            // Single tap interrupted by another key
+            sim.tap(0, ms(3000), tap).unwrap();
+            sim.tap(1, ms(3100), tap).unwrap();
+            sim.run_until(ms(4000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(250), ms(350), ms(3100), ms(3200)]
+            );
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(1150), ms(1250)]);
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(2300), ms(2500)]
+            );
+            let first_c = sim.reports().iter().find(|(_, r)| r.is_pressed(Key::C));
+            assert!(first_c.is_some_and(|(_, r)| r.is_pressed(Key::A)));
+        }
+    }
+
+    mod combo {
+        use super::*;
+        use crate::{
+            behavior::{HoldTapBehavior, HoldTapFlavor, MomentaryLayerBehavior},
+            combo::Combo,
+            error::Error,
+            layer::Layer,
+        };
+
+        /// Simulator for `layers` with `combos` set
+        fn combo_sim<const LAYERS: usize, const POSITIONS: usize>(
+            layers: [[Binding; POSITIONS]; LAYERS],
+            combos: &'static [Combo],
+        ) -> Simulator<LAYERS, POSITIONS> {
+            let mut sim = simulator(layers);
+            sim.state_mut().set_combos(combos);
+            sim
+        }
+
+        #[test]
+        fn combos() {
+            static COMBOS: [Combo; 2] = [
+                Combo::new(&[0, 1], ManualBehavior::Simple(kp(Key::X))),
+                Combo::new(&[0, 1, 2], ManualBehavior::Simple(kp(Key::Y))),
+            ];
+            let mut sim = combo_sim([[key(Key::A), key(Key::B), key(Key::C)]], &COMBOS);
+
+            // Could still become the longer combo until the combo timeout runs out
+            sim.press(0, ms(0)).unwrap();
+            sim.press(1, ms(10)).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            sim.release(1, ms(110)).unwrap();
+            // Longest combo, complete right away
+            sim.press(0, ms(1000)).unwrap();
+            sim.press(1, ms(1010)).unwrap();
+            sim.press(2, ms(1020)).unwrap();
+            sim.release(2, ms(1100)).unwrap();
+            sim.release(0, ms(1110)).unwrap();
+            sim.release(1, ms(1120)).unwrap();
+            // Released before anything else was pressed
+            sim.tap(0, ms(2000), TAP).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::X), [ms(50), ms(100)]);
+            assert_eq!(key_changes(sim.reports(), Key::Y), [ms(1020), ms(1100)]);
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(2020), ms(2020)]);
+            assert!(key_changes(sim.reports(), Key::B).is_empty());
+            assert!(key_changes(sim.reports(), Key::C).is_empty());
+        }
+
+        #[test]
+        fn positions_past_the_keymap_are_rejected() {
+            static COMBOS: [Combo; 1] = [Combo::new(&[0, 1], ManualBehavior::Simple(kp(Key::X)))];
+            let mut sim = combo_sim([[key(Key::A), key(Key::B)]], &COMBOS);
+
+            // Where the combo's behavior is pressed internally
+            assert_eq!(sim.press(2, ms(0)), Err(Error::InvalidPosition));
+            assert_eq!(sim.release(2, ms(100)), Err(Error::InvalidPosition));
+            sim.run_until(ms(1000)).unwrap();
+
+            assert!(key_changes(sim.reports(), Key::X).is_empty());
+        }
+
+        #[test]
+        fn combo_layers() {
+            static COMBOS: [Combo; 1] = [Combo::new(&[0, 1], ManualBehavior::Simple(kp(Key::X)))
+                .with_layers(&[Layer::new(1)])];
+            let mo = SimpleBehavior::MomentaryLayer(MomentaryLayerBehavior::new(Layer::new(1)));
+            let mut sim = combo_sim(
+                [
+                    [
+                        key(Key::A),
+                        key(Key::B),
+                        Binding::Behavior(ManualBehavior::Simple(mo)),
+                    ],
+                    [
+                        Binding::Transparent,
+                        Binding::Transparent,
+                        Binding::Transparent,
+                    ],
+                ],
+                &COMBOS,
+            );
+
+            sim.press(0, ms(0)).unwrap();
+            sim.press(1, ms(10)).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            sim.release(1, ms(100)).unwrap();
+            sim.press(2, ms(1000)).unwrap();
+            sim.press(0, ms(1100)).unwrap();
+            sim.press(1, ms(1110)).unwrap();
+            sim.release(0, ms(1200)).unwrap();
+            sim.release(1, ms(1200)).unwrap();
+            sim.release(2, ms(1300)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(0), ms(100)]);
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(10), ms(100)]);
+            assert_eq!(key_changes(sim.reports(), Key::X), [ms(1110), ms(1200)]);
+        }
+
+        #[test]
+        fn combo_keys_interrupt_hold_tap() {
+            static COMBOS: [Combo; 1] = [Combo::new(&[1, 2], ManualBehavior::Simple(kp(Key::X)))];
+            let ht = HoldTapBehavior::new(kp(Key::LeftShift), kp(Key::A))
+                .with_flavor(HoldTapFlavor::HoldPreferred);
+            let mut sim = combo_sim(
+                [[
+                    Binding::Behavior(ManualBehavior::HoldTap(ht)),
+                    key(Key::B),
+                    key(Key::C),
+                ]],
+                &COMBOS,
+            );
+
+            sim.press(0, ms(0)).unwrap();
+            // Decides the hold-tap while still waiting for the rest of the combo
+            sim.press(1, ms(100)).unwrap();
+            sim.press(2, ms(120)).unwrap();
+            sim.release(1, ms(150)).unwrap();
+            sim.release(2, ms(150)).unwrap();
+            sim.release(0, ms(180)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(100), ms(180)]
+            );
+            assert_eq!(key_changes(sim.reports(), Key::X), [ms(120), ms(150)]);
+            assert!(key_changes(sim.reports(), Key::A).is_empty());
+        }
+    }
+
+    mod sticky_key {
+        use super::*;
+        use crate::behavior::StickyKeyBehavior;
+
+        /// Position 0 is a sticky shift released after 500ms, position 1 is `other`
+        fn sticky_sim(other: Key) -> Simulator<1, 2> {
+            let sticky = StickyKeyBehavior::new(kp(Key::LeftShift))
+                .with_release_after(Duration::from_millis(500));
+            simulator([[
+                Binding::Behavior(ManualBehavior::StickyKey(sticky)),
+                key(other),
+            ]])
+        }
+
+        #[test]
+        fn sticky_key() {
+            let mut sim = sticky_sim(Key::A);
+
+            // Latched for the next key
+            sim.tap(0, ms(0), TAP).unwrap();
+            sim.tap(1, ms(100), TAP).unwrap();
+            // Plain hold
+            sim.press(0, ms(1000)).unwrap();
+            sim.tap(1, ms(1050), TAP).unwrap();
+            sim.release(0, ms(1100)).unwrap();
+            // Latched, but nothing follows
+            sim.tap(0, ms(2000), TAP).unwrap();
+            // Locked by a double tap
+            sim.tap(0, ms(3000), TAP).unwrap();
+            sim.tap(0, ms(3100), TAP).unwrap();
+            sim.tap(1, ms(3200), TAP).unwrap();
+            sim.tap(1, ms(3300), TAP).unwrap();
+            sim.tap(0, ms(3400), TAP).unwrap();
+            // Tapped again after the tapping term, only latched again
+            sim.tap(0, ms(5000), TAP).unwrap();
+            sim.tap(0, ms(5300), TAP).unwrap();
+            sim.tap(1, ms(5400), TAP).unwrap();
+            sim.run_until(ms(6000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [
+                    ms(0),
+                    ms(120),
+                    ms(1000),
+                    ms(1100),
+                    ms(2000),
+                    ms(2520),
+                    ms(3000),
+                    ms(3400),
+                    ms(5000),
+                    ms(5420)
+                ]
+            );
+        }
+
+        #[test]
+        fn timeout_ignores_other_keys() {
+            let mut sim = sticky_sim(Key::LeftControl);
+
+            sim.tap(0, ms(0), TAP).unwrap();
+            // A modifier doesn't use it up, and doesn't restart its timeout either
+            sim.tap(1, ms(400), Duration::from_millis(200)).unwrap();
+            sim.run_until(ms(2000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::LeftShift), [ms(0), ms(520)]);
+        }
+    }
+
+    mod one_shot_layer {
+        use super::*;
+        use crate::{behavior::OneShotLayerBehavior, layer::Layer};
+
+        /// One-shot layer 1, with the default timeout
+        fn osl() -> Binding {
+            Binding::Behavior(ManualBehavior::OneShotLayer(OneShotLayerBehavior::new(
+                Layer::new(1),
+            )))
+        }
+
+        #[test]
+        fn one_shot_layer() {
+            let mut sim = simulator([[osl(), key(Key::A)], [Binding::Transparent, key(Key::B)]]);
+
+            // Only the next key is on layer 1
+            sim.tap(0, ms(0), TAP).unwrap();
+            sim.tap(1, ms(100), TAP).unwrap();
+            sim.tap(1, ms(200), TAP).unwrap();
+            // Held, it works like a momentary layer
+            sim.press(0, ms(1000)).unwrap();
+            sim.tap(1, ms(1050), TAP).unwrap();
+            sim.tap(1, ms(1100), TAP).unwrap();
+            sim.release(0, ms(1200)).unwrap();
+            // A stray tap times out
+            sim.tap(0, ms(2000), TAP).unwrap();
+            sim.tap(1, ms(3100), TAP).unwrap();
+            sim.run_until(ms(4000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::B),
+                [ms(100), ms(120), ms(1050), ms(1070), ms(1100), ms(1120)]
+            );
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(200), ms(220), ms(3100), ms(3120)]
+            );
+        }
+
+        #[test]
+        fn timeout_ignores_other_keys() {
+            let mut sim = simulator([
+                [osl(), key(Key::A), key(Key::B)],
+                [Binding::Transparent, Binding::Transparent, key(Key::C)],
+            ]);
+
+            sim.press(1, ms(0)).unwrap();
+            sim.tap(0, ms(100), TAP).unwrap();
+            // Releasing a key pressed before it doesn't restart the timeout
+            sim.release(1, ms(500)).unwrap();
+            sim.tap(2, ms(1200), TAP).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(1200), ms(1220)]);
+            assert!(key_changes(sim.reports(), Key::C).is_empty());
+        }
+    }
+
+    mod caps_word {
+        use super::*;
+        use crate::behavior::CapsWordBehavior;
+
+        #[test]
+        fn caps_word() {
+            let caps_word = CapsWordBehavior::new()
+                .with_shift_minus(true)
+                .with_idle_timeout(Duration::from_millis(500));
+            let mut sim = simulator([[
+                Binding::Behavior(ManualBehavior::CapsWord(caps_word)),
+                key(Key::A),
+                key(Key::Minus),
+                key(Key::Space),
+            ]]);
+
+            // Ended by space
+            sim.tap(0, ms(0), TAP).unwrap();
+            sim.tap(1, ms(100), TAP).unwrap();
+            sim.tap(2, ms(200), TAP).unwrap();
+            sim.tap(3, ms(300), TAP).unwrap();
+            sim.tap(1, ms(400), TAP).unwrap();
+            // Toggled off
+            sim.tap(0, ms(1000), TAP).unwrap();
+            sim.tap(0, ms(1100), TAP).unwrap();
+            sim.tap(1, ms(1200), TAP).unwrap();
+            // Timed out
+            sim.tap(0, ms(2000), TAP).unwrap();
+            sim.tap(1, ms(2400), TAP).unwrap();
+            sim.tap(1, ms(3000), TAP).unwrap();
+            sim.run_until(ms(4000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(100), ms(120), ms(200), ms(220), ms(2400), ms(2420)]
+            );
+            assert_eq!(key_changes(sim.reports(), Key::A).len(), 10);
+        }
+
+        #[test]
+        fn long_word_keeps_one_timeout() {
+            let mut sim = simulator([[
+                Binding::Behavior(ManualBehavior::CapsWord(CapsWordBehavior::new())),
+                key(Key::A),
+            ]]);
+
+            // Every key pushes the timeout back, more keys than the timer queue has room for
+            sim.tap(0, ms(0), TAP).unwrap();
+            for i in 1..=40 {
+                sim.tap(1, ms(i * 100), TAP).unwrap();
+            }
+            sim.run_until(ms(10_000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::LeftShift).len(), 80);
+            assert_eq!(key_changes(sim.reports(), Key::A).len(), 80);
+        }
+    }
+
+    mod key_repeat {
+        use super::*;
+        use crate::behavior::KeyRepeatBehavior;
+
+        #[test]
+        fn key_repeat() {
+            let mut sim = simulator([[
+                key(Key::LeftShift),
+                key(Key::A),
+                Binding::Behavior(ManualBehavior::KeyRepeat(KeyRepeatBehavior::new())),
+            ]]);
+
+            // Repeats shifted A, held as long as the repeat key is
+            sim.press(0, ms(0)).unwrap();
+            sim.tap(1, ms(50), TAP).unwrap();
+            sim.release(0, ms(100)).unwrap();
+            sim.tap(2, ms(200), Duration::from_millis(200)).unwrap();
+            // Shift on its own doesn't count
+            sim.tap(0, ms(500), TAP).unwrap();
+            sim.tap(2, ms(600), TAP).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::A),
+                [ms(50), ms(70), ms(200), ms(400), ms(600), ms(620)]
+            );
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [
+                    ms(0),
+                    ms(100),
+                    ms(200),
+                    ms(400),
+                    ms(500),
+                    ms(520),
+                    ms(600),
+                    ms(620)
+                ]
+            );
+        }
+    }
+
+    mod macros {
+        use super::*;
+        use crate::{
+            behavior::{MacroBehavior, MacroStep, MomentaryLayerBehavior},
+            layer::Layer,
+        };
+
+        /// `steps` with 20ms taps and 10ms between steps
+        fn macro_binding(steps: &'static [MacroStep]) -> Binding {
+            Binding::Behavior(ManualBehavior::Macro(
+                MacroBehavior::new(steps)
+                    .with_tap_duration(TAP)
+                    .with_wait(Duration::from_millis(10)),
+            ))
+        }
+
+        #[test]
+        fn steps() {
+            static STEPS: [MacroStep; 6] = [
+                MacroStep::Tap(Key::A),
+                MacroStep::Press(Key::LeftShift),
+                MacroStep::Tap(Key::B),
+                MacroStep::Release(Key::LeftShift),
+                MacroStep::PauseForRelease,
+                MacroStep::Tap(Key::C),
+            ];
+            let mut sim = simulator([[macro_binding(&STEPS)]]);
+
+            sim.tap(0, ms(0), Duration::from_millis(500)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(0), ms(20)]);
+            assert_eq!(key_changes(sim.reports(), Key::LeftShift), [ms(30), ms(70)]);
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(40), ms(60)]);
+            assert_eq!(key_changes(sim.reports(), Key::C), [ms(500), ms(520)]);
+        }
+
+        #[test]
+        fn interrupted_macro_releases_its_keys() {
+            static STEPS: [MacroStep; 3] = [
+                MacroStep::Press(Key::LeftShift),
+                MacroStep::Wait(Duration::from_millis(500)),
+                MacroStep::Release(Key::LeftShift),
+            ];
+            let mo = SimpleBehavior::MomentaryLayer(MomentaryLayerBehavior::new(Layer::new(1)));
+            let mut sim = simulator([
+                [
+                    macro_binding(&STEPS),
+                    Binding::Behavior(ManualBehavior::Simple(mo)),
+                ],
+                [key(Key::B), Binding::Transparent],
+            ]);
+
+            sim.tap(0, ms(0), TAP).unwrap();
+            // Same position on another layer, while the macro still holds shift
+            sim.press(1, ms(100)).unwrap();
+            sim.tap(0, ms(200), TAP).unwrap();
+            sim.release(1, ms(300)).unwrap();
+            sim.run_until(ms(1000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::LeftShift), [ms(0), ms(200)]);
+            assert_eq!(key_changes(sim.reports(), Key::B), [ms(200), ms(220)]);
+        }
+    }
+
+    mod leader {
+        use super::*;
+        use crate::behavior::{HoldTapBehavior, LeaderBehavior, LeaderSequence};
+
+        #[test]
+        fn sequences() {
+            static SEQUENCES: [LeaderSequence; 2] = [
+                LeaderSequence::new(&[Key::G, Key::C], ManualBehavior::Simple(kp(Key::X))),
+                LeaderSequence::new(&[Key::G, Key::G], ManualBehavior::Simple(kp(Key::Y))),
+            ];
+            // In a static, so an unsorted table doesn't compile
+            static LEADER: LeaderBehavior =
+                LeaderBehavior::new(&SEQUENCES).with_timeout(Duration::from_millis(500));
+            let mut sim = simulator([[
+                Binding::Behavior(ManualBehavior::Leader(LEADER.clone())),
+                key(Key::G),
+                key(Key::C),
+                key(Key::A),
+            ]]);
+
+            // Matches, tapped for the default tap duration
+            sim.tap(0, ms(0), TAP).unwrap();
+            sim.tap(1, ms(100), TAP).unwrap();
+            sim.tap(2, ms(200), TAP).unwrap();
+            // No sequence starts with G A, which is swallowed
+            sim.tap(0, ms(1000), TAP).unwrap();
+            sim.tap(1, ms(1100), TAP).unwrap();
+            sim.tap(3, ms(1200), TAP).unwrap();
+            sim.tap(3, ms(1300), TAP).unwrap();
+            // Times out halfway
+            sim.tap(0, ms(2000), TAP).unwrap();
+            sim.tap(1, ms(2100), TAP).unwrap();
+            sim.tap(1, ms(3000), TAP).unwrap();
+            sim.run_until(ms(4000)).unwrap();
+
+            assert_eq!(key_changes(sim.reports(), Key::X), [ms(200), ms(300)]);
+            assert!(key_changes(sim.reports(), Key::Y).is_empty());
+            assert!(key_changes(sim.reports(), Key::C).is_empty());
+            assert_eq!(key_changes(sim.reports(), Key::A), [ms(1300), ms(1320)]);
+            assert_eq!(key_changes(sim.reports(), Key::G), [ms(3000), ms(3020)]);
+        }
+
+        #[test]
+        fn modifiers_pass_through() {
+            static SEQUENCES: [LeaderSequence; 1] = [LeaderSequence::new(
+                &[Key::G, Key::C],
+                ManualBehavior::Simple(kp(Key::X)),
+            )];
+            let ht = HoldTapBehavior::new(kp(Key::LeftShift), kp(Key::A));
+            let mut sim = simulator([[
+                Binding::Behavior(ManualBehavior::Leader(LeaderBehavior::new(&SEQUENCES))),
+                Binding::Behavior(ManualBehavior::HoldTap(ht)),
+                key(Key::G),
+                key(Key::C),
+            ]]);
+
+            sim.tap(0, ms(0), TAP).unwrap();
+            // Decides on hold halfway through the sequence
+            sim.press(1, ms(100)).unwrap();
+            sim.tap(2, ms(400), TAP).unwrap();
+            sim.tap(3, ms(500), TAP).unwrap();
+            sim.release(1, ms(700)).unwrap();
+            sim.run_until(ms(2000)).unwrap();
+
+            assert_eq!(
+                key_changes(sim.reports(), Key::LeftShift),
+                [ms(300), ms(700)]
+            );
+            assert_eq!(key_changes(sim.reports(), Key::X), [ms(500), ms(600)]);
+            assert!(key_changes(sim.reports(), Key::G).is_empty());
+        }
+    }
 }
@@ -0,0 +1,243 @@
+use crate::{behavior::ManualBehavior, error::Error, timer::Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Handle to a behavior instance in a `BehaviorRegistry`. Slots are reused, but each reuse gets a
+/// new generation, so a handle to a behavior that has since been removed never refers to a
+/// different behavior that took its slot.
+pub struct BehaviorId {
+    index: u16,
+    generation: u16,
+}
+
+#[derive(Debug)]
+struct Slot {
+    generation: u16,
+    behavior: Option<ManualBehavior>,
+    deadline: Option<Instant>,
+    // Instant of the timer event that is queued for the behavior's timeout, if any
+    scheduled: Option<Instant>,
+}
+
+/// Arena owning the behavior instances that are currently live (held keys, and released ones that
+/// still have something to do), handing out a `BehaviorId` for each of them
+#[derive(Debug)]
+pub struct BehaviorRegistry<const N: usize> {
+    slots: [Slot; N],
+}
+
+impl<const N: usize> Default for BehaviorRegistry<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BehaviorRegistry<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const {
+                Slot {
+                    generation: 0,
+                    behavior: None,
+                    deadline: None,
+                    scheduled: None,
+                }
+            }; N],
+        }
+    }
+
+    pub fn insert(&mut self, behavior: ManualBehavior) -> Result<BehaviorId, Error> {
+        let (index, slot) = self
+            .slots
+            .iter_mut()
+            .enumerate()
+            .find(|(_, s)| s.behavior.is_none())
+            .ok_or(Error::TooManyHeldBehaviors)?;
+
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.behavior = Some(behavior);
+        slot.deadline = None;
+        slot.scheduled = None;
+
+        Ok(BehaviorId {
+            index: index as u16,
+            generation: slot.generation,
+        })
+    }
+
+    pub fn get(&self, id: BehaviorId) -> Option<&ManualBehavior> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.behavior.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: BehaviorId) -> Option<&mut ManualBehavior> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.behavior.as_mut())
+    }
+
+    pub fn remove(&mut self, id: BehaviorId) -> Result<ManualBehavior, Error> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|s| s.generation == id.generation)
+            .and_then(|s| s.behavior.take())
+            .ok_or(Error::UnknownBehavior)
+    }
+
+    pub fn contains(&self, id: BehaviorId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// The instant the behavior's timeout is armed for. A timer event that fires before it is
+    /// stale and shouldn't be delivered.
+    pub fn deadline(&self, id: BehaviorId) -> Option<Instant> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|s| s.generation == id.generation && s.behavior.is_some())
+            .and_then(|s| s.deadline)
+    }
+
+    pub fn set_deadline(&mut self, id: BehaviorId, deadline: Option<Instant>) {
+        if let Some(slot) = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|s| s.generation == id.generation && s.behavior.is_some())
+        {
+            slot.deadline = deadline;
+        }
+    }
+
+    /// The instant of the timer event queued for the behavior's timeout. It can be earlier than
+    /// `deadline` if the timeout was pushed back since, then the event only schedules another one.
+    pub fn scheduled(&self, id: BehaviorId) -> Option<Instant> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|s| s.generation == id.generation && s.behavior.is_some())
+            .and_then(|s| s.scheduled)
+    }
+
+    pub fn set_scheduled(&mut self, id: BehaviorId, scheduled: Option<Instant>) {
+        if let Some(slot) = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|s| s.generation == id.generation && s.behavior.is_some())
+        {
+            slot.scheduled = scheduled;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BehaviorId, &ManualBehavior)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.behavior.as_ref().map(|b| {
+                (
+                    BehaviorId {
+                        index: index as u16,
+                        generation: slot.generation,
+                    },
+                    b,
+                )
+            })
+        })
+    }
+
+    /// Handles of every live behavior. This is a snapshot, so the registry can be modified while
+    /// iterating over it.
+    pub fn ids(&self) -> impl Iterator<Item = BehaviorId> + use<N> {
+        let ids: [Option<BehaviorId>; N] = core::array::from_fn(|index| {
+            let slot = &self.slots[index];
+            slot.behavior.as_ref().map(|_| BehaviorId {
+                index: index as u16,
+                generation: slot.generation,
+            })
+        });
+
+        ids.into_iter().flatten()
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.behavior = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{KeyPressBehavior, KeyRepeatBehavior, SimpleBehavior},
+        vboard::Key,
+    };
+
+    // Two behaviors that can be told apart
+    fn key_press() -> ManualBehavior {
+        ManualBehavior::Simple(SimpleBehavior::KeyPress(KeyPressBehavior::new(Key::A)))
+    }
+
+    fn key_repeat() -> ManualBehavior {
+        ManualBehavior::KeyRepeat(KeyRepeatBehavior::new())
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut registry = BehaviorRegistry::<2>::new();
+        let a = registry.insert(key_press()).unwrap();
+        let b = registry.insert(key_repeat()).unwrap();
+
+        assert_ne!(a, b);
+        assert!(matches!(registry.get(a), Some(ManualBehavior::Simple(_))));
+        assert!(matches!(
+            registry.get(b),
+            Some(ManualBehavior::KeyRepeat(_))
+        ));
+        assert_eq!(
+            registry.insert(key_press()).err(),
+            Some(Error::TooManyHeldBehaviors)
+        );
+
+        assert!(registry.remove(a).is_ok());
+        assert!(!registry.contains(a));
+        assert_eq!(registry.remove(a).err(), Some(Error::UnknownBehavior));
+        assert_eq!(registry.ids().collect::<std::vec::Vec<_>>(), [b]);
+    }
+
+    #[test]
+    fn stale_id_never_resolves_to_reused_slot() {
+        let mut registry = BehaviorRegistry::<1>::new();
+        let old = registry.insert(key_press()).unwrap();
+        registry.remove(old).unwrap();
+
+        // Same slot, new generation
+        let new = registry.insert(key_repeat()).unwrap();
+        assert_ne!(old, new);
+        assert!(registry.get(old).is_none());
+        assert!(registry.get_mut(old).is_none());
+        assert!(!registry.contains(old));
+        assert_eq!(registry.remove(old).err(), Some(Error::UnknownBehavior));
+        assert!(matches!(
+            registry.get(new),
+            Some(ManualBehavior::KeyRepeat(_))
+        ));
+    }
+
+    #[test]
+    fn stale_id_has_no_deadline() {
+        let mut registry = BehaviorRegistry::<1>::new();
+        let old = registry.insert(key_press()).unwrap();
+        registry.set_deadline(old, Some(Instant::from_millis(100)));
+        assert_eq!(registry.deadline(old), Some(Instant::from_millis(100)));
+
+        registry.remove(old).unwrap();
+        assert_eq!(registry.deadline(old), None);
+
+        // The new behavior doesn't inherit the deadline, and the old id can't set one for it
+        let new = registry.insert(key_repeat()).unwrap();
+        assert_eq!(registry.deadline(new), None);
+        registry.set_deadline(new, Some(Instant::from_millis(200)));
+        registry.set_deadline(old, Some(Instant::from_millis(300)));
+        assert_eq!(registry.deadline(old), None);
+        assert_eq!(registry.deadline(new), Some(Instant::from_millis(200)));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::vboard::{Key, KeyboardState};
+
+/// Boot protocol keyboard report: modifier byte, reserved byte, then six key slots
+pub const BOOT_REPORT_LEN: usize = 8;
+pub const BOOT_REPORT_KEYS: usize = 6;
+/// Usage reported in every key slot when more keys are held than fit in the report
+pub const ERROR_ROLL_OVER: u8 = 0x01;
+/// One bit per usage below the modifiers (0x00-0xDF), the modifiers have their own byte
+pub const NKRO_BITMAP_LEN: usize = 28;
+/// NKRO report: modifier byte followed by the key bitmap
+pub const NKRO_REPORT_LEN: usize = 1 + NKRO_BITMAP_LEN;
+pub const MAX_REPORT_LEN: usize = NKRO_REPORT_LEN;
+
+/// Report descriptor matching `NkroReport`. Boot protocol reports don't need one, the host
+/// uses the fixed layout from the HID specification.
+pub const NKRO_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xA1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Keyboard/Keypad)
+    0x19, 0xE0, //   Usage Minimum (Left Control)
+    0x29, 0xE7, //   Usage Maximum (Right GUI)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x19, 0x00, //   Usage Minimum (0x00)
+    0x29, 0xDF, //   Usage Maximum (0xDF)
+    0x95, 0xE0, //   Report Count (224)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0xC0, // End Collection
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportMode {
+    /// 6KRO boot protocol reports, understood by every host including BIOSes
+    #[default]
+    Boot,
+    /// Bitmap reports, any number of keys can be held at once
+    Nkro,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardReport {
+    Boot(BootReport),
+    Nkro(NkroReport),
+}
+
+impl KeyboardReport {
+    pub fn new(mode: ReportMode, state: &KeyboardState) -> Self {
+        match mode {
+            ReportMode::Boot => Self::Boot(BootReport::new(state)),
+            ReportMode::Nkro => Self::Nkro(NkroReport::new(state)),
+        }
+    }
+
+    /// Report with nothing pressed
+    pub fn empty(mode: ReportMode) -> Self {
+        match mode {
+            ReportMode::Boot => Self::Boot(BootReport::default()),
+            ReportMode::Nkro => Self::Nkro(NkroReport::default()),
+        }
+    }
+
+    pub fn mode(&self) -> ReportMode {
+        match self {
+            KeyboardReport::Boot(_) => ReportMode::Boot,
+            KeyboardReport::Nkro(_) => ReportMode::Nkro,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::empty(self.mode())
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        match self {
+            KeyboardReport::Boot(r) => r.is_pressed(key),
+            KeyboardReport::Nkro(r) => r.is_pressed(key),
+        }
+    }
+
+    /// Write the report bytes to the start of `buf` and return how many were written. `buf`
+    /// must be at least `MAX_REPORT_LEN` long.
+    pub fn copy_to(&self, buf: &mut [u8]) -> usize {
+        match self {
+            KeyboardReport::Boot(r) => {
+                buf[..BOOT_REPORT_LEN].copy_from_slice(&r.as_bytes());
+                BOOT_REPORT_LEN
+            }
+            KeyboardReport::Nkro(r) => {
+                buf[..NKRO_REPORT_LEN].copy_from_slice(&r.as_bytes());
+                NKRO_REPORT_LEN
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 6KRO boot protocol keyboard report, as defined in appendix B.1 of the HID specification
+pub struct BootReport {
+    pub modifiers: u8,
+    pub keys: [u8; BOOT_REPORT_KEYS],
+}
+
+impl BootReport {
+    /// Build the report for the keys currently held. Modifiers always go in the modifier byte,
+    /// other keys fill the key slots in the order they were pressed. If there are more than six
+    /// of them every slot is set to `ERROR_ROLL_OVER`, the modifiers are still reported.
+    pub fn new(state: &KeyboardState) -> Self {
+        let mut report = Self::default();
+        let mut len = 0;
+        let mut overflow = false;
+
+        for (key, _) in state.held_keys.iter() {
+            if let Some(bit) = key.modifier_bit() {
+                report.modifiers |= bit;
+                continue;
+            }
+
+            let usage: u8 = (*key).into();
+            if report.keys[..len].contains(&usage) {
+                // Same key held by more than one behavior
+                continue;
+            }
+
+            if len == BOOT_REPORT_KEYS {
+                overflow = true;
+            } else {
+                report.keys[len] = usage;
+                len += 1;
+            }
+        }
+
+        if overflow {
+            report.keys = [ERROR_ROLL_OVER; BOOT_REPORT_KEYS];
+        }
+
+        report
+    }
+
+    pub fn as_bytes(&self) -> [u8; BOOT_REPORT_LEN] {
+        let mut bytes = [0; BOOT_REPORT_LEN];
+        bytes[0] = self.modifiers;
+        bytes[2..].copy_from_slice(&self.keys);
+        bytes
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        match key.modifier_bit() {
+            Some(bit) => self.modifiers & bit != 0,
+            None => self.keys.contains(&key.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// NKRO report, a modifier byte followed by one bit per usage. Described by
+/// `NKRO_REPORT_DESCRIPTOR`.
+pub struct NkroReport {
+    pub modifiers: u8,
+    pub keys: [u8; NKRO_BITMAP_LEN],
+}
+
+impl NkroReport {
+    pub fn new(state: &KeyboardState) -> Self {
+        let mut report = Self::default();
+
+        for (key, _) in state.held_keys.iter() {
+            if let Some(bit) = key.modifier_bit() {
+                report.modifiers |= bit;
+            } else {
+                let usage: u8 = (*key).into();
+                report.keys[usage as usize / 8] |= 1 << (usage % 8);
+            }
+        }
+
+        report
+    }
+
+    pub fn as_bytes(&self) -> [u8; NKRO_REPORT_LEN] {
+        let mut bytes = [0; NKRO_REPORT_LEN];
+        bytes[0] = self.modifiers;
+        bytes[1..].copy_from_slice(&self.keys);
+        bytes
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        match key.modifier_bit() {
+            Some(bit) => self.modifiers & bit != 0,
+            None => {
+                let usage: u8 = key.into();
+                self.keys[usage as usize / 8] & (1 << (usage % 8)) != 0
+            }
+        }
+    }
+}
+
+/// Remembers the last report that was sent to the host, so unchanged reports can be skipped
+#[derive(Debug, Default)]
+pub struct ReportTracker<R> {
+    last_sent: Option<R>,
+}
+
+impl<R> ReportTracker<R>
+where
+    R: Copy + PartialEq,
+{
+    pub const fn new() -> Self {
+        Self { last_sent: None }
+    }
+
+    /// Whether `report` differs from the last one marked as sent
+    pub fn changed(&self, report: &R) -> bool {
+        self.last_sent.as_ref() != Some(report)
+    }
+
+    pub fn last_sent(&self) -> Option<&R> {
+        self.last_sent.as_ref()
+    }
+
+    pub fn mark_sent(&mut self, report: R) {
+        self.last_sent = Some(report);
+    }
+
+    /// Forget the last sent report, so the next one counts as changed. Useful after the host
+    /// resets or reconfigures the device.
+    pub fn reset(&mut self) {
+        self.last_sent = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{KeyPressBehavior, ManualBehavior, SimpleBehavior},
+        registry::BehaviorRegistry,
+    };
+
+    /// Keyboard state with `keys` held, in that order
+    fn held(keys: &[Key]) -> KeyboardState {
+        let mut registry = BehaviorRegistry::<1>::new();
+        let id = registry
+            .insert(ManualBehavior::Simple(SimpleBehavior::KeyPress(
+                KeyPressBehavior::new(Key::A),
+            )))
+            .unwrap();
+        let mut state = KeyboardState::new();
+        for key in keys {
+            state.press(*key, id).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn boot_report_bytes() {
+        let report = BootReport::new(&held(&[Key::LeftShift, Key::A, Key::B, Key::RightAlt]));
+
+        assert_eq!(
+            report.as_bytes(),
+            [0x42, 0x00, 0x04, 0x05, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn boot_report_modifier_bits() {
+        let modifiers = [
+            (Key::LeftControl, 0x01),
+            (Key::LeftShift, 0x02),
+            (Key::LeftAlt, 0x04),
+            (Key::LeftGUI, 0x08),
+            (Key::RightControl, 0x10),
+            (Key::RightShift, 0x20),
+            (Key::RightAlt, 0x40),
+            (Key::RightGUI, 0x80),
+        ];
+        for (key, bit) in modifiers {
+            let report = BootReport::new(&held(&[key]));
+            assert_eq!(report.modifiers, bit);
+            assert_eq!(report.keys, [0; BOOT_REPORT_KEYS]);
+        }
+    }
+
+    #[test]
+    fn boot_report_duplicate_keys() {
+        let report = BootReport::new(&held(&[Key::A, Key::A, Key::B]));
+
+        assert_eq!(report.keys, [0x04, 0x05, 0x00, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn boot_report_overflow() {
+        let report = BootReport::new(&held(&[
+            Key::A,
+            Key::B,
+            Key::C,
+            Key::LeftShift,
+            Key::D,
+            Key::E,
+            Key::F,
+            Key::G,
+        ]));
+
+        assert_eq!(report.keys, [ERROR_ROLL_OVER; BOOT_REPORT_KEYS]);
+        assert_eq!(report.modifiers, 0x02);
+    }
+
+    #[test]
+    fn nkro_report_bits() {
+        let report = NkroReport::new(&held(&[Key::LeftShift, Key::A, Key::Space, Key::A]));
+        let bytes = report.as_bytes();
+
+        assert_eq!(bytes[0], 0x02);
+        // A is usage 0x04, space 0x2C
+        assert_eq!(bytes[1], 0x10);
+        assert_eq!(bytes[1 + 5], 0x10);
+        assert_eq!(bytes.iter().map(|b| b.count_ones()).sum::<u32>(), 3);
+        assert!(report.is_pressed(Key::Space) && !report.is_pressed(Key::B));
+    }
+
+    #[test]
+    fn nkro_descriptor_matches_report() {
+        // Report Count of the key bitmap
+        let count = NKRO_REPORT_DESCRIPTOR
+            .windows(2)
+            .rfind(|w| w[0] == 0x95)
+            .map(|w| w[1] as usize);
+
+        assert_eq!(count, Some(NKRO_BITMAP_LEN * 8));
+    }
+
+    #[test]
+    fn tracker_changed() {
+        let a = KeyboardReport::new(ReportMode::Boot, &held(&[Key::A]));
+        let empty = KeyboardReport::empty(ReportMode::Boot);
+        let mut tracker = ReportTracker::new();
+
+        assert!(tracker.changed(&a));
+        tracker.mark_sent(a);
+        assert!(!tracker.changed(&a));
+        assert!(tracker.changed(&empty));
+
+        tracker.reset();
+        assert!(tracker.changed(&a));
+    }
+}
@@ -0,0 +1,143 @@
+//! Host-side simulation, for testing behaviors without hardware. Needs the `std` feature.
+
+use std::{cell::Cell, rc::Rc, vec::Vec};
+
+use crate::{
+    config::EngineConfig,
+    error::Error,
+    keymap::Keymap,
+    report::KeyboardReport,
+    state::State,
+    timer::{Duration, Instant, Timer},
+};
+
+/// Deterministic `Timer`, time only moves when it is advanced explicitly. Clones share the same
+/// clock, so a test can keep one while the `State` owns another.
+#[derive(Debug, Clone)]
+pub struct VirtualTimer {
+    now: Rc<Cell<Instant>>,
+}
+
+impl Default for VirtualTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VirtualTimer {
+    pub fn new() -> Self {
+        Self {
+            now: Rc::new(Cell::new(Instant::from_micros(0))),
+        }
+    }
+
+    /// Move the clock to `instant`. Panics if that would move it backwards.
+    pub fn set(&self, instant: Instant) {
+        assert!(instant >= self.now.get(), "virtual time can't go backwards");
+        self.now.set(instant);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.set(self.now.get() + duration);
+    }
+}
+
+impl Timer for VirtualTimer {
+    fn as_instant(&self) -> Instant {
+        self.now.get()
+    }
+
+    fn add_duration(&self, duration: Duration) -> Instant {
+        self.now.get() + duration
+    }
+
+    fn wait(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Drives a `State` on a `VirtualTimer`. Key events are fed at given instants, timer events fire
+/// exactly at their deadlines in between, and every report the host would receive is recorded
+/// with the instant it was produced at.
+pub struct Simulator<const LAYERS: usize, const POSITIONS: usize> {
+    state: State<VirtualTimer, LAYERS, POSITIONS>,
+    timer: VirtualTimer,
+    reports: Vec<(Instant, KeyboardReport)>,
+}
+
+impl<const LAYERS: usize, const POSITIONS: usize> Simulator<LAYERS, POSITIONS> {
+    pub fn new(keymap: Keymap<LAYERS, POSITIONS>, config: EngineConfig) -> Self {
+        let timer = VirtualTimer::new();
+        let state = State::new(timer.clone(), keymap, config);
+
+        Self {
+            state,
+            timer,
+            reports: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &State<VirtualTimer, LAYERS, POSITIONS> {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State<VirtualTimer, LAYERS, POSITIONS> {
+        &mut self.state
+    }
+
+    pub fn now(&self) -> Instant {
+        self.timer.as_instant()
+    }
+
+    /// Every report sent so far, with the instant it was sent at
+    pub fn reports(&self) -> &[(Instant, KeyboardReport)] {
+        &self.reports
+    }
+
+    pub fn press(&mut self, position: usize, at: Instant) -> Result<(), Error> {
+        self.run_until(at)?;
+        self.state.key_down(position, at)?;
+        self.step()
+    }
+
+    pub fn release(&mut self, position: usize, at: Instant) -> Result<(), Error> {
+        self.run_until(at)?;
+        self.state.key_up(position, at)?;
+        self.step()
+    }
+
+    /// Press and release `position`, holding it for `duration`
+    pub fn tap(&mut self, position: usize, at: Instant, duration: Duration) -> Result<(), Error> {
+        self.press(position, at)?;
+        self.release(position, at + duration)
+    }
+
+    /// Advance time to `at`, firing every timer event on the way at its own instant
+    pub fn run_until(&mut self, at: Instant) -> Result<(), Error> {
+        loop {
+            let next = self.state.poll()?;
+            self.record();
+
+            match next {
+                Some(deadline) if deadline <= at => self.timer.set(deadline),
+                _ => break,
+            }
+        }
+
+        self.timer.set(at);
+        self.step()
+    }
+
+    fn step(&mut self) -> Result<(), Error> {
+        self.state.poll()?;
+        self.record();
+        Ok(())
+    }
+
+    fn record(&mut self) {
+        while let Some(report) = self.state.report() {
+            self.reports.push((self.now(), report));
+            self.state.mark_report_sent(report);
+        }
+    }
+}
@@ -1,152 +1,1302 @@
-use static_collections::{HashMap, PriorityQueue, Queue};
+use static_collections::{HashMap, List, PriorityQueue, Queue};
 
 use crate::{
-    behavior::{BehaviorComplex, BehaviorSimple, ManualBehavior},
-    event::{BehaviorEvent, ComplexKeyEvent, Event, EventData, KeyEvent, SimpleKeyEvent},
-    timer::{BehaviorTimeoutEvent, Duration, Timer, TimerEvent, UntapBehaviorEvent, UntapKeyEvent},
-    vboard::KeyboardState,
+    behavior::{BehaviorComplex, BehaviorSimple, ManualBehavior, PressContext},
+    combo::{ActiveCombo, Combo, ComboRelease, MAX_ACTIVE_COMBOS, MAX_COMBO_KEYS},
+    config::EngineConfig,
+    error::Error,
+    event::{
+        BehaviorEvent, ComplexKeyEvent, Event, EventData, KeyEvent, LayerEvent, PositionEvent,
+        SimpleKeyEvent,
+    },
+    keymap::Keymap,
+    layer::{Layer, LayerStack},
+    registry::{BehaviorId, BehaviorRegistry},
+    report::{KeyboardReport, ReportMode, ReportTracker},
+    timer::{
+        BehaviorTimeoutEvent, ComboTimeoutEvent, Instant, Timer, TimerEvent, UntapBehaviorEvent,
+        UntapKeyEvent, UntapManualEvent,
+    },
+    vboard::{Key, KeyCode, KeyboardState, MAX_HELD_KEYS, Modifiers},
 };
 
 pub const MAX_HELD_BEHAVIORS: usize = 20;
 const HELD_BEH_BACK_ARR_LEN: usize = MAX_HELD_BEHAVIORS * 2;
 pub const MAX_EVENTS: usize = 100;
 pub const MAX_TIMER_EVENTS: usize = 50;
+pub const MAX_BUFFERED_EVENTS: usize = 20;
+pub const MAX_PENDING_REPORTS: usize = 16;
 
-pub struct State<T>
+// Position recorded for released behaviors that were tapped by another behavior rather than
+// pressed by a key, see `BehaviorEvent::TapManualBehavior`. No key can press it again.
+const DETACHED_POSITION: usize = usize::MAX;
+
+/// The engine state, fed with `key_down`/`key_up` and driven by `poll`.
+///
+/// Key presses that could be the start of a combo are held back first, until the combo is
+/// complete or can't happen anymore. A combo's behavior is pressed at position
+/// `POSITIONS + index`, everything past this point sees it like any other key.
+///
+/// While any behavior is undecided (e.g. a hold-tap that doesn't know yet whether it's a hold),
+/// other key events are shown to it but otherwise held back. Once nothing is undecided anymore
+/// they are replayed in order with their original instants, so a key pressed during a hold-tap
+/// that turns into a hold ends up after the hold's modifier. A behavior pressed during the replay
+/// can become undecided itself, the rest of the buffer then waits for that one.
+///
+/// Errors: all the storage here is fixed-size. If any of it runs out (`Error::is_capacity`), the
+/// engine gives up on the current state entirely rather than continuing in some partial state:
+/// every key and behavior is released, all queued and timer events are dropped and the layer
+/// stack is reset to the base layer, then the error is returned. Keys that are still physically
+/// held do nothing until they are pressed again. Any other error is returned as soon as it
+/// happens and drops the event that caused it. Queued events, due timer events and held back key
+/// events that weren't reached yet are kept, and handled by the next `poll` or key event.
+pub struct State<T, const LAYERS: usize, const POSITIONS: usize>
 where
     T: Timer,
 {
-    // The number of elements in the HashMap's backing array
-    held_behaviors: HashMap<usize, ManualBehavior, HELD_BEH_BACK_ARR_LEN>,
-    event_queue: Queue<Event, MAX_EVENTS>,
+    keymap: Keymap<LAYERS, POSITIONS>,
+    combos: &'static [Combo],
+    config: EngineConfig,
+    behaviors: BehaviorRegistry<MAX_HELD_BEHAVIORS>,
+    // Behaviors that are currently pressed, keyed by the position that pressed them, with the
+    // layer they were resolved on. The constant is the number of elements in the HashMap's
+    // backing array
+    held_behaviors: HashMap<usize, (Layer, BehaviorId), HELD_BEH_BACK_ARR_LEN>,
+    // Behaviors that were released but aren't done yet (see `BehaviorComplex::is_done`), with the
+    // position and layer they were pressed on
+    released_behaviors: List<(usize, Layer, BehaviorId), MAX_HELD_BEHAVIORS>,
+    // Events with the instant of the key or timer event that caused them
+    event_queue: Queue<(Event, Instant), MAX_EVENTS>,
+    // Instant of the key or timer event being handled, or of the queued event being applied.
+    // Events pushed meanwhile are tagged with it.
+    now: Instant,
     timer: T,
     timer_events: PriorityQueue<TimerEvent, MAX_TIMER_EVENTS>,
     keyboard_state: KeyboardState,
+    // Modifiers that watching behaviors added to keys as they were pressed (see
+    // `BehaviorComplex::extra_modifiers`), released along with the key
+    added_modifiers: List<(Key, BehaviorId, Modifiers), MAX_HELD_KEYS>,
+    // Key presses that a behavior captured instead of sending them (see
+    // `BehaviorComplex::captures_key`), whose releases are dropped too
+    captured_keys: List<(Key, BehaviorId), MAX_HELD_KEYS>,
+    layers: LayerStack,
+    last_press: Option<Instant>,
+    // Most recent key press that `repeatable` accepted, with the modifiers held at the time
+    last_key: Option<KeyCode>,
+    repeatable: fn(Key) -> bool,
+    // Presses held back while they could still become a combo, when they stop waiting, and how
+    // many of them the undecided behaviors have been shown so far
+    combo_keys: List<PositionEvent, MAX_COMBO_KEYS>,
+    combo_deadline: Option<Instant>,
+    notified_combo_keys: usize,
+    active_combos: List<ActiveCombo, MAX_ACTIVE_COMBOS>,
+    // Position events held back while a behavior is undecided, and how many of them the undecided
+    // behaviors have been shown so far
+    buffered_events: List<PositionEvent, MAX_BUFFERED_EVENTS>,
+    notified_events: usize,
+    // Every distinct keyboard state since the last report that was sent, so that e.g. a press and
+    // release applied in the same `poll` still reach the host as two reports
+    pending_reports: Queue<KeyboardReport, MAX_PENDING_REPORTS>,
+    reports: ReportTracker<KeyboardReport>,
+    report_mode: ReportMode,
+    // Set while switching report format, until the host has seen every key released
+    pending_report_mode: Option<ReportMode>,
 }
 
-impl<T> State<T>
+impl<T, const LAYERS: usize, const POSITIONS: usize> State<T, LAYERS, POSITIONS>
 where
     T: Timer,
 {
-    pub fn apply_event(&mut self, event: Event) {
+    pub fn new(timer: T, keymap: Keymap<LAYERS, POSITIONS>, config: EngineConfig) -> Self {
+        Self {
+            keymap,
+            combos: &[],
+            config,
+            behaviors: BehaviorRegistry::new(),
+            held_behaviors: HashMap::new(),
+            released_behaviors: List::new(),
+            event_queue: Queue::new(),
+            now: Instant::from_micros(0),
+            timer,
+            timer_events: PriorityQueue::new(),
+            keyboard_state: KeyboardState::new(),
+            added_modifiers: List::new(),
+            captured_keys: List::new(),
+            layers: LayerStack::new(),
+            last_press: None,
+            last_key: None,
+            repeatable: |key| key.is_repeatable(),
+            combo_keys: List::new(),
+            combo_deadline: None,
+            notified_combo_keys: 0,
+            active_combos: List::new(),
+            buffered_events: List::new(),
+            notified_events: 0,
+            pending_reports: Queue::new(),
+            reports: ReportTracker::new(),
+            report_mode: ReportMode::Boot,
+            pending_report_mode: None,
+        }
+    }
+
+    /// The currently active layers, see `LayerStack` for how they are ordered
+    pub fn layers(&self) -> &LayerStack {
+        &self.layers
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Changes apply to everything scheduled from now on, timer events that are already pending
+    /// keep their instants
+    pub fn config_mut(&mut self) -> &mut EngineConfig {
+        &mut self.config
+    }
+
+    pub fn keymap_mut(&mut self) -> &mut Keymap<LAYERS, POSITIONS> {
+        &mut self.keymap
+    }
+
+    /// Applies to keys pressed from now on
+    pub fn set_combos(&mut self, combos: &'static [Combo]) {
+        self.combos = combos;
+    }
+
+    /// Which keys are remembered for key repeat, by default everything but modifiers
+    pub fn set_repeatable(&mut self, repeatable: fn(Key) -> bool) {
+        self.repeatable = repeatable;
+    }
+
+    /// Physical key press at `position`. The behavior is resolved through the active layers now
+    /// and kept until the matching `key_up`, so layer changes while the key is held don't affect
+    /// which behavior gets released. Positions past the keymap's are rejected with
+    /// `Error::InvalidPosition`.
+    pub fn key_down(&mut self, position: usize, instant: Instant) -> Result<(), Error> {
+        if position >= POSITIONS {
+            return Err(Error::InvalidPosition);
+        }
+
+        let res = self.combo_press(PositionEvent {
+            position,
+            pressed: true,
+            instant,
+        });
+        self.degrade(res)
+    }
+
+    /// Physical key release at `position`, releases whichever behavior `key_down` resolved
+    pub fn key_up(&mut self, position: usize, instant: Instant) -> Result<(), Error> {
+        if position >= POSITIONS {
+            return Err(Error::InvalidPosition);
+        }
+
+        let res = self.combo_release(PositionEvent {
+            position,
+            pressed: false,
+            instant,
+        });
+        self.degrade(res)
+    }
+
+    fn combo_press(&mut self, event: PositionEvent) -> Result<(), Error> {
+        if self.combo_keys.iter().any(|e| e.position == event.position) {
+            // Already pressed, e.g. switch bounce
+            return Ok(());
+        }
+
+        if !self.combo_keys.is_empty() && self.combo_candidates(Some(event)).next().is_none() {
+            // Can't be part of the combo the held back keys started
+            self.resolve_combo_keys()?;
+        }
+        if self.combo_candidates(Some(event)).next().is_none() {
+            return self.handle_position_event(event, false);
+        }
+
+        self.combo_keys
+            .push_back(event)
+            .map_err(|_| Error::TooManyBufferedEvents)?;
+
+        // Undecided behaviors see the key right away, e.g. a hold-tap held together with the combo
+        // can decide on it. Whatever they decide applies to the keys buffered before this one.
+        if self.has_undecided() {
+            self.now = event.instant;
+            self.notify_others(event.position, true, false)?;
+            self.notified_combo_keys = self.combo_keys.len();
+            self.replay_buffered()?;
+        }
+
+        let len = self.combo_keys.len();
+        let complete = self
+            .combo_candidates(None)
+            .any(|c| c.positions().len() == len);
+        let longer = self
+            .combo_candidates(None)
+            .any(|c| c.positions().len() > len);
+        if complete && !longer {
+            return self.resolve_combo_keys();
+        }
+
+        let first = self
+            .combo_keys
+            .iter()
+            .next()
+            .map_or(event.instant, |e| e.instant);
+        let timeout = self
+            .combo_candidates(None)
+            .map(|c| c.timeout(&self.config))
+            .max();
+        if let Some(timeout) = timeout {
+            self.combo_deadline = Some(first + timeout);
+            self.schedule(TimerEvent::Combo(ComboTimeoutEvent {
+                instant: first + timeout,
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    fn combo_release(&mut self, event: PositionEvent) -> Result<(), Error> {
+        if self.combo_keys.iter().any(|e| e.position == event.position) {
+            // Released before the combo was complete
+            self.resolve_combo_keys()?;
+        }
+
+        let combos = self.combos;
+        let bit = |a: &ActiveCombo| {
+            combos[a.index]
+                .positions()
+                .iter()
+                .position(|p| *p == event.position)
+                .map(|i| 1 << i)
+                .filter(|bit| a.held & bit != 0)
+        };
+        let Some(mut active) = self
+            .active_combos
+            .iter()
+            .find(|a| bit(a).is_some())
+            .copied()
+        else {
+            return self.handle_position_event(event, false);
+        };
+
+        self.active_combos.remove_by(|a| *a == active);
+        active.held &= !bit(&active).unwrap_or(0);
+
+        let release = match combos[active.index].release() {
+            ComboRelease::AnyReleased => true,
+            ComboRelease::AllReleased => active.held == 0,
+        };
+        if release && !active.released {
+            active.released = true;
+            self.handle_position_event(
+                PositionEvent {
+                    position: POSITIONS + active.index,
+                    pressed: false,
+                    instant: event.instant,
+                },
+                false,
+            )?;
+        }
+
+        if active.held != 0 {
+            self.active_combos
+                .push_back(active)
+                .map_err(|_| Error::TooManyActiveCombos)?;
+        }
+
+        Ok(())
+    }
+
+    /// Combos the held back keys, plus `extra`, can still turn into
+    fn combo_candidates(&self, extra: Option<PositionEvent>) -> impl Iterator<Item = &Combo> {
+        let layer = self.layers.top();
+        let first = self
+            .combo_keys
+            .iter()
+            .chain(extra.iter())
+            .next()
+            .map(|e| e.instant);
+
+        self.combos.iter().filter(move |c| {
+            c.is_active_on(layer)
+                && self.combo_keys.iter().chain(extra.iter()).all(|e| {
+                    c.contains(e.position)
+                        && first.is_some_and(|first| e.instant - first <= c.timeout(&self.config))
+                })
+        })
+    }
+
+    /// Press the combo with the most positions out of the held back keys, if they make up one,
+    /// and pass the rest of them on as normal presses
+    fn resolve_combo_keys(&mut self) -> Result<(), Error> {
+        self.combo_deadline = None;
+        let keys = core::mem::replace(&mut self.combo_keys, List::new());
+        let notified = core::mem::replace(&mut self.notified_combo_keys, 0);
+
+        let layer = self.layers.top();
+        let combos = self.combos;
+        let combo = combos
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                c.is_active_on(layer)
+                    && c.positions()
+                        .iter()
+                        .all(|p| keys.iter().any(|e| e.position == *p))
+            })
+            .max_by_key(|(_, c)| c.positions().len());
+
+        // The combo is pressed in place of its last key, the others keep their order
+        let mut remaining = combo.map_or(0, |(_, c)| c.positions().len());
+        for (i, event) in keys.iter().enumerate() {
+            match combo {
+                Some((index, c)) if c.contains(event.position) => {
+                    remaining -= 1;
+                    if remaining == 0 {
+                        self.active_combos
+                            .push_back(ActiveCombo {
+                                index,
+                                held: u8::MAX >> (8 - c.positions().len()),
+                                released: false,
+                            })
+                            .map_err(|_| Error::TooManyActiveCombos)?;
+                        // The combo's own position is new to everyone
+                        self.handle_position_event(
+                            PositionEvent {
+                                position: POSITIONS + index,
+                                pressed: true,
+                                instant: event.instant,
+                            },
+                            false,
+                        )?;
+                    }
+                }
+                _ => self.handle_position_event(*event, i < notified)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `notified` is set if the undecided behaviors were already shown `event` while it was held
+    /// back for a combo
+    fn handle_position_event(&mut self, event: PositionEvent, notified: bool) -> Result<(), Error> {
+        if self.is_own_event(&event) || (!self.has_undecided() && self.buffered_events.is_empty()) {
+            self.process_position_event(event)?;
+        } else {
+            if !notified {
+                self.now = event.instant;
+                self.notify_others(event.position, event.pressed, false)?;
+            }
+            self.buffered_events
+                .push_back(event)
+                .map_err(|_| Error::TooManyBufferedEvents)?;
+            self.notified_events = self.buffered_events.len();
+        }
+
+        self.replay_buffered()
+    }
+
+    fn process_position_event(&mut self, event: PositionEvent) -> Result<(), Error> {
+        // Events queue in breadth-first order, so apply everything caused by earlier key events
+        // (e.g. a hold-tap deciding) before this one can add anything
+        self.process_events()?;
+
+        self.now = event.instant;
+        if event.pressed {
+            self.press_position(event.position, event.instant)
+        } else {
+            self.release_position(event.position, event.instant)
+        }
+    }
+
+    /// Work through the buffered events. While something is undecided the ones it hasn't seen yet
+    /// are shown to it, otherwise the oldest one is processed.
+    fn replay_buffered(&mut self) -> Result<(), Error> {
+        loop {
+            if self.has_undecided() {
+                let Some(event) = self
+                    .buffered_events
+                    .iter()
+                    .nth(self.notified_events)
+                    .copied()
+                else {
+                    return Ok(());
+                };
+
+                if self.is_own_event(&event) {
+                    self.buffered_events.remove_by(|e| *e == event);
+                    self.process_position_event(event)?;
+                } else {
+                    self.notified_events += 1;
+                    self.now = event.instant;
+                    self.notify_others(event.position, event.pressed, false)?;
+                }
+            } else {
+                let Some(event) = self.buffered_events.remove_by(|_| true) else {
+                    return Ok(());
+                };
+                // Whatever becomes undecided now hasn't seen any of the remaining events
+                self.notified_events = 0;
+                self.process_position_event(event)?;
+            }
+        }
+    }
+
+    fn has_undecided(&self) -> bool {
+        self.behaviors.iter().any(|(_, b)| b.is_undecided())
+    }
+
+    /// Whether `event` is the next step of an undecided behavior at its position, i.e. its release
+    /// or a press that continues it after it was released. That is what decides it, so it can't
+    /// wait until it has decided.
+    fn is_own_event(&self, event: &PositionEvent) -> bool {
+        let id = if event.pressed {
+            self.released_behaviors
+                .iter()
+                .find(|(p, _, _)| *p == event.position)
+                .map(|(_, _, id)| *id)
+        } else {
+            self.held_behaviors.get(&event.position).map(|(_, id)| *id)
+        };
+
+        id.and_then(|id| self.behaviors.get(id))
+            .is_some_and(|b| b.is_undecided())
+    }
+
+    fn press_position(&mut self, position: usize, instant: Instant) -> Result<(), Error> {
+        if self.held_behaviors.contains_key(&position) {
+            // Already pressed, e.g. switch bounce
+            return Ok(());
+        }
+
+        let resolved = self.resolve(position);
+
+        // A behavior that is still around from the last press of the same binding gets this
+        // press. Anything else left at this position is interrupted like any other behavior, then
+        // dropped.
+        let released = self
+            .released_behaviors
+            .iter()
+            .find(|(p, _, _)| *p == position)
+            .copied();
+        let reused = match (released, &resolved) {
+            (Some((_, l, id)), Some((layer, _))) if l == *layer => Some(id),
+            _ => None,
+        };
+        if reused.is_none() {
+            self.released_behaviors
+                .remove_by(|(p, _, _)| *p == position);
+        }
+
+        self.notify_others(position, true, true)?;
+
+        if reused.is_none()
+            && let Some((_, _, id)) = released
+        {
+            self.drop_unfinished(id)?;
+        }
+
+        let Some((layer, behavior)) = resolved else {
+            return Ok(());
+        };
+        let id = match reused {
+            Some(id) => {
+                self.released_behaviors.remove_by(|(_, _, i)| *i == id);
+                id
+            }
+            None => self.behaviors.insert(behavior)?,
+        };
+        if self.held_behaviors.insert(position, (layer, id)).is_err() {
+            let _ = self.behaviors.remove(id);
+            return Err(Error::TooManyHeldBehaviors);
+        }
+
+        let ctx = PressContext {
+            instant,
+            last_press: self.last_press.replace(instant),
+            last_key: self.last_key,
+        };
+        let res = self.press_behavior(id, &ctx);
+        if self.behaviors.get(id).is_none() {
+            // Refused the press, there is nothing left to release
+            self.held_behaviors.remove(&position);
+        }
+        res
+    }
+
+    /// The behavior for `position` and the layer it was found on. Positions past the keymap's are
+    /// combos, which always count as being on the top layer.
+    fn resolve(&self, position: usize) -> Option<(Layer, ManualBehavior)> {
+        match position.checked_sub(POSITIONS) {
+            Some(index) => self
+                .combos
+                .get(index)
+                .map(|c| (self.layers.top(), c.behavior().clone())),
+            None => self
+                .keymap
+                .resolve(&self.layers, position)
+                .map(|(layer, behavior)| (layer, behavior.clone())),
+        }
+    }
+
+    fn release_position(&mut self, position: usize, instant: Instant) -> Result<(), Error> {
+        let Some((layer, id)) = self.held_behaviors.remove(&position) else {
+            return Ok(());
+        };
+
+        self.unpress_behavior(id, position, layer, instant)?;
+        self.notify_others(position, false, true)
+    }
+
+    /// Hand a press to behavior `id`. It is dropped if it can't take it.
+    fn press_behavior(&mut self, id: BehaviorId, ctx: &PressContext) -> Result<(), Error> {
+        let event = match self.behaviors.get_mut(id).map(|b| b.on_press(id, ctx)) {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                let _ = self.drop_unfinished(id);
+                return Err(e);
+            }
+            None => return Err(Error::UnknownBehavior),
+        };
+        if let Some(event) = event {
+            self.push_event(event)?;
+        }
+
+        self.arm_timeout(id, ctx.instant)
+    }
+
+    /// Hand an unpress to behavior `id`, pressed from `position` on `layer`. It is dropped if it
+    /// is done, or kept in `released_behaviors` until it is.
+    fn unpress_behavior(
+        &mut self,
+        id: BehaviorId,
+        position: usize,
+        layer: Layer,
+        instant: Instant,
+    ) -> Result<(), Error> {
+        let event = match self.behaviors.get_mut(id).map(|b| b.on_unpress(id)) {
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                let _ = self.drop_unfinished(id);
+                return Err(e);
+            }
+            None => return Err(Error::UnknownBehavior),
+        };
+        if let Some(event) = event {
+            self.push_event(event)?;
+        }
+
+        self.arm_timeout(id, instant)?;
+        if self.behaviors.get(id).is_some_and(|b| b.is_done()) {
+            self.behaviors.remove(id)?;
+        } else if self
+            .released_behaviors
+            .push_back((position, layer, id))
+            .is_err()
+        {
+            let _ = self.drop_unfinished(id);
+            return Err(Error::TooManyHeldBehaviors);
+        }
+
+        Ok(())
+    }
+
+    /// Schedule the behavior's next timeout, `from` is the instant of whatever it just reacted to.
+    /// This replaces any timeout it had pending.
+    fn arm_timeout(&mut self, id: BehaviorId, from: Instant) -> Result<(), Error> {
+        let deadline = self
+            .behaviors
+            .get(id)
+            .and_then(|b| b.get_duration(&self.config))
+            .map(|dur| from + dur);
+        self.behaviors.set_deadline(id, deadline);
+
+        // A timer event that is already queued for an earlier instant reschedules itself when it
+        // fires (see `apply_timer_event`), so a behavior that keeps pushing its timeout back
+        // doesn't fill the timer queue
+        match deadline {
+            Some(instant) if self.behaviors.scheduled(id).is_none_or(|s| instant < s) => {
+                self.schedule_timeout(id, instant)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn schedule_timeout(&mut self, id: BehaviorId, instant: Instant) -> Result<(), Error> {
+        self.schedule(TimerEvent::Behavior(BehaviorTimeoutEvent {
+            behavior_id: id,
+            instant,
+        }))?;
+        self.behaviors.set_scheduled(id, Some(instant));
+
+        Ok(())
+    }
+
+    /// Tell every undecided behavior that another position was pressed or released, and with
+    /// `listening` also the decided ones that still want to know (`is_listening`). Their events
+    /// are queued before anything the key itself does, so e.g. a hold-tap deciding on hold gets
+    /// its modifier in before the interrupting key.
+    fn notify_others(
+        &mut self,
+        position: usize,
+        pressed: bool,
+        listening: bool,
+    ) -> Result<(), Error> {
+        for id in self.behaviors.ids() {
+            let Some(behavior) = self.behaviors.get_mut(id) else {
+                continue;
+            };
+            if !(behavior.is_undecided() || listening && behavior.is_listening()) {
+                continue;
+            }
+            // A released behavior isn't interrupted by its own key
+            if self
+                .released_behaviors
+                .iter()
+                .any(|(p, _, i)| *p == position && *i == id)
+            {
+                continue;
+            }
+
+            let event = if pressed {
+                behavior.on_other_press(id, position)?
+            } else {
+                behavior.on_other_release(id, position)?
+            };
+            if let Some(event) = event {
+                self.push_event(event)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Show a key event to every behavior that watches keys (`BehaviorComplex::watches_keys`),
+    /// except the one that caused it
+    fn notify_key_watchers(
+        &mut self,
+        key_event: &SimpleKeyEvent,
+        source: BehaviorId,
+    ) -> Result<(), Error> {
+        for id in self.behaviors.ids() {
+            if id != source && self.behaviors.get(id).is_some_and(|b| b.watches_keys()) {
+                self.send_key_event(id, key_event, source)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_key_event(
+        &mut self,
+        id: BehaviorId,
+        key_event: &SimpleKeyEvent,
+        source: BehaviorId,
+    ) -> Result<(), Error> {
+        let Some(behavior) = self.behaviors.get_mut(id) else {
+            return Err(Error::UnknownBehavior);
+        };
+        if let Some(event) = behavior.on_key_event(id, key_event, source)? {
+            self.push_event(event)?;
+        }
+        if self
+            .behaviors
+            .get(id)
+            .is_some_and(|b| b.rearms_on_key_event())
+        {
+            self.arm_timeout(id, self.now)?;
+        }
+        self.drop_if_done(id)
+    }
+
+    /// The behavior that a press of `code` by `source` goes to instead of the host, if any
+    fn capturing_behavior(&self, code: &KeyCode, source: BehaviorId) -> Option<BehaviorId> {
+        self.behaviors
+            .iter()
+            .find(|(id, b)| *id != source && b.watches_keys() && b.captures_key(code))
+            .map(|(id, _)| id)
+    }
+
+    /// Modifiers the behaviors watching keys want added to `code`, other than `source` itself and
+    /// the ones `code` already has
+    fn extra_modifiers(&self, code: &KeyCode, source: BehaviorId) -> Modifiers {
+        self.behaviors
+            .iter()
+            .filter(|(id, b)| *id != source && b.watches_keys())
+            .fold(Modifiers::NONE, |mods, (_, b)| {
+                mods | b.extra_modifiers(code)
+            })
+            .difference(code.modifiers)
+    }
+
+    fn press_code(&mut self, code: KeyCode, behavior_id: BehaviorId) -> Result<(), Error> {
+        let extra = self.extra_modifiers(&code, behavior_id);
+        self.keyboard_state
+            .press_code(code.with_modifiers(extra), behavior_id)?;
+        if !extra.is_empty() {
+            self.added_modifiers
+                .push_back((code.key, behavior_id, extra))
+                .map_err(|_| Error::TooManyHeldKeys)?;
+        }
+        if (self.repeatable)(code.key) {
+            let own = Modifiers::from_bits(code.key.modifier_bit().unwrap_or(0));
+            self.last_key = Some(
+                KeyCode::new(code.key)
+                    .with_modifiers(self.keyboard_state.modifiers().difference(own)),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Release `code` along with any modifiers that were added when it was pressed. Returns false
+    /// if the press was captured instead, in which case there is nothing to release.
+    fn release_code(&mut self, code: KeyCode, behavior_id: BehaviorId) -> bool {
+        if self
+            .captured_keys
+            .remove_by(|(k, id)| *k == code.key && *id == behavior_id)
+            .is_some()
+        {
+            return false;
+        }
+
+        self.keyboard_state.release_code(code, behavior_id);
+        if let Some((_, _, extra)) = self
+            .added_modifiers
+            .remove_by(|(k, id, _)| *k == code.key && *id == behavior_id)
+        {
+            for key in extra.keys() {
+                self.keyboard_state.release(key, behavior_id);
+            }
+        }
+        true
+    }
+
+    /// Release `code` and let the key watchers know
+    fn unpress_code(&mut self, code: KeyCode, behavior_id: BehaviorId) -> Result<(), Error> {
+        if self.release_code(code, behavior_id) {
+            self.notify_key_watchers(&SimpleKeyEvent::Unpress(code), behavior_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Press a copy of `behavior` that no key owns, and release it after the tap duration
+    fn tap_detached(&mut self, behavior: ManualBehavior) -> Result<(), Error> {
+        let id = self.behaviors.insert(behavior)?;
+        let instant = self.now;
+        let ctx = PressContext {
+            instant,
+            last_press: self.last_press,
+            last_key: self.last_key,
+        };
+
+        self.press_behavior(id, &ctx)?;
+        self.schedule(TimerEvent::UntapManual(UntapManualEvent {
+            behavior_id: id,
+            instant: instant + self.config.tap_duration,
+        }))
+    }
+
+    /// Drop a released behavior once it has nothing left to do
+    fn drop_if_done(&mut self, id: BehaviorId) -> Result<(), Error> {
+        if self.behaviors.get(id).is_some_and(|b| b.is_done())
+            && self
+                .released_behaviors
+                .remove_by(|(_, _, i)| *i == id)
+                .is_some()
+        {
+            self.behaviors.remove(id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drop a behavior before it finished, releasing any keys it still holds so none get stuck
+    fn drop_unfinished(&mut self, id: BehaviorId) -> Result<(), Error> {
+        let _ = self.behaviors.remove(id);
+        while self.captured_keys.remove_by(|(_, i)| *i == id).is_some() {}
+        while self
+            .added_modifiers
+            .remove_by(|(_, i, _)| *i == id)
+            .is_some()
+        {}
+        while let Some((key, _)) = self.keyboard_state.held_keys.remove_by(|(_, i)| *i == id) {
+            self.notify_key_watchers(&SimpleKeyEvent::Unpress(KeyCode::new(key)), id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Release every key and behavior and drop everything that is queued, see the type docs
+    pub fn release_all(&mut self) {
+        self.behaviors.clear();
+        self.held_behaviors = HashMap::new();
+        self.released_behaviors = List::new();
+        self.event_queue = Queue::new();
+        self.timer_events = PriorityQueue::new();
+        self.keyboard_state.release_all();
+        self.added_modifiers = List::new();
+        self.captured_keys = List::new();
+        self.layers.reset();
+        self.buffered_events = List::new();
+        self.notified_events = 0;
+        self.combo_keys = List::new();
+        self.combo_deadline = None;
+        self.notified_combo_keys = 0;
+        self.active_combos = List::new();
+
+        // The host has to see everything released, even if that means dropping the reports it
+        // hasn't been sent yet
+        if self.queue_report().is_err() {
+            self.pending_reports = Queue::new();
+            let _ = self.queue_report();
+        }
+    }
+
+    /// Apply the degradation policy to the result of one of the public entry points
+    fn degrade<R>(&mut self, res: Result<R, Error>) -> Result<R, Error> {
+        if let Err(e) = &res
+            && e.is_capacity()
+        {
+            self.release_all();
+        }
+
+        res
+    }
+
+    fn push_event(&mut self, event: Event) -> Result<(), Error> {
+        self.event_queue
+            .push_back((event, self.now))
+            .map_err(|_| Error::EventQueueFull)
+    }
+
+    fn schedule(&mut self, event: TimerEvent) -> Result<(), Error> {
+        self.timer_events
+            .insert(event)
+            .map_err(|_| Error::TimerQueueFull)
+    }
+
+    pub fn timer(&self) -> &T {
+        &self.timer
+    }
+
+    /// Run the engine until it's idle. Queued events are applied first, then every timer event
+    /// that is due is fired in instant order, and the events each one causes are applied before
+    /// the next one fires.
+    ///
+    /// Returns the instant of the next scheduled timer event, or `None` if nothing is scheduled.
+    /// The main loop can sleep until then, e.g. with
+    /// `state.timer().wait(deadline - state.timer().as_instant())`, unless a key event arrives
+    /// first.
+    pub fn poll(&mut self) -> Result<Option<Instant>, Error> {
+        let res = self.run_due();
+        self.degrade(res)?;
+
+        Ok(self.next_deadline())
+    }
+
+    fn run_due(&mut self) -> Result<(), Error> {
+        self.process_events()?;
+
+        let now = self.timer.as_instant();
+        while self.timer_events.peek().is_some_and(|e| e.instant() <= now) {
+            if let Some(e) = self.timer_events.pop() {
+                self.apply_timer_event(e)?;
+                self.queue_report()?;
+                self.process_events()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The next report to send to the host, if the keyboard state changed since the last one
+    /// passed to `mark_report_sent`. Keep sending until this returns `None`, every intermediate
+    /// state is kept so short presses aren't lost.
+    pub fn report(&self) -> Option<KeyboardReport> {
+        self.pending_reports.iter().next().copied()
+    }
+
+    /// Record that `report` was delivered to the host
+    pub fn mark_report_sent(&mut self, report: KeyboardReport) {
+        if self.pending_reports.iter().next() == Some(&report) {
+            self.pending_reports.pop_front();
+        }
+        self.reports.mark_sent(report);
+        if self.pending_report_mode.is_some() {
+            // Room for the empty report of a mode switch, if it didn't fit when it was requested
+            let _ = self.queue_report();
+        }
+
+        if report.is_empty()
+            && self.pending_reports.is_empty()
+            && let Some(mode) = self.pending_report_mode.take()
+        {
+            self.report_mode = mode;
+            self.reports.reset();
+            // Can't fail, the queue is empty
+            let _ = self.queue_report();
+        }
+    }
+
+    /// Queue a report for the current keyboard state if it differs from the last queued or sent
+    /// one. Fails if the host fell too far behind, nothing is dropped to make room.
+    fn queue_report(&mut self) -> Result<(), Error> {
+        let report = match self.pending_report_mode {
+            // Keys pressed since the switch started are held back until the new format is in use
+            Some(_) => KeyboardReport::empty(self.report_mode),
+            None => KeyboardReport::new(self.report_mode, &self.keyboard_state),
+        };
+
+        let changed = match self.pending_reports.iter().last() {
+            Some(last) => *last != report,
+            // The host starts out with nothing pressed
+            None if self.reports.last_sent().is_none() => !report.is_empty(),
+            None => self.reports.changed(&report),
+        };
+        if !changed {
+            return Ok(());
+        }
+
+        self.pending_reports
+            .push_back(report)
+            .map_err(|_| Error::ReportQueueFull)
+    }
+
+    pub fn report_mode(&self) -> ReportMode {
+        self.report_mode
+    }
+
+    /// Switch report format. Every held key is released first. The reports still pending are sent
+    /// as they are, followed by an empty report in the old format, before any report in the new
+    /// one, so no key can get stuck on the host side.
+    pub fn set_report_mode(&mut self, mode: ReportMode) {
+        if mode == self.pending_report_mode.unwrap_or(self.report_mode) {
+            return;
+        }
+
+        self.keyboard_state.release_all();
+
+        if self.pending_reports.is_empty() && self.reports.last_sent().is_none_or(|r| r.is_empty())
+        {
+            // Host already thinks nothing is pressed
+            self.report_mode = mode;
+            self.pending_report_mode = None;
+            self.reports.reset();
+        } else {
+            self.pending_report_mode = Some(mode);
+            // If the queue is full, `mark_report_sent` adds it once there is room
+            let _ = self.queue_report();
+        }
+    }
+
+    /// Instant of the next scheduled timer event
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timer_events.peek().map(|e| e.instant())
+    }
+
+    fn process_events(&mut self) -> Result<(), Error> {
+        while let Some((event, instant)) = self.event_queue.pop_front() {
+            self.now = instant;
+            self.apply_event(event)?;
+            self.queue_report()?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_event(&mut self, event: Event) -> Result<(), Error> {
         match event.data {
             EventData::KeyEvent(ke) => {
                 match ke {
                     KeyEvent::Complex(e) => match e {
                         ComplexKeyEvent::ReleaseTap(key, key1) => {
                             // Unrelease first key
-                            self.keyboard_state.held_keys.remove_by(|(k, _)| *k == key);
+                            self.unpress_code(key, event.behavior_id)?;
                             // Add push event for tapped key
-                            self.event_queue.push_back(Event::new(
+                            self.push_event(Event::new(
                                 event.behavior_id,
                                 EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Press(key1))),
-                            ));
+                            ))?;
                             // Add timed release event for tapped key
-                            self.timer_events
-                                .insert(TimerEvent::UntapKey(UntapKeyEvent {
-                                    instant: self.timer.add_duration(Duration::from_millis(100)),
-                                    key: key1,
-                                }));
+                            self.schedule(TimerEvent::UntapKey(UntapKeyEvent {
+                                instant: self.now + self.config.tap_duration,
+                                behavior_id: event.behavior_id,
+                                key: key1,
+                            }))?;
                         }
                         ComplexKeyEvent::ReleasePress(key, key1) => {
                             // Unrelease first key
-                            self.keyboard_state.held_keys.remove_by(|(k, _)| *k == key);
+                            self.unpress_code(key, event.behavior_id)?;
                             // Add push event for tapped key
-                            self.event_queue.push_back(Event::new(
+                            self.push_event(Event::new(
                                 event.behavior_id,
                                 EventData::KeyEvent(KeyEvent::Simple(SimpleKeyEvent::Press(key1))),
-                            ));
+                            ))?;
                         }
                     },
                     KeyEvent::Simple(e) => match e {
-                        SimpleKeyEvent::Press(key) => self
-                            .keyboard_state
-                            .held_keys
-                            .push_back((key, event.behavior_id)),
-                        SimpleKeyEvent::Unpress(key) => {
-                            self.keyboard_state.held_keys.remove_by(|(k, _)| *k == key);
+                        SimpleKeyEvent::Press(code) => {
+                            if let Some(id) = self.capturing_behavior(&code, event.behavior_id) {
+                                self.captured_keys
+                                    .push_back((code.key, event.behavior_id))
+                                    .map_err(|_| Error::TooManyHeldKeys)?;
+                                self.send_key_event(id, &e, event.behavior_id)?;
+                            } else {
+                                self.press_code(code, event.behavior_id)?;
+                                self.notify_key_watchers(&e, event.behavior_id)?;
+                            }
+                        }
+                        SimpleKeyEvent::Unpress(code) => {
+                            self.unpress_code(code, event.behavior_id)?;
                         }
                     },
                 }
             }
             EventData::BehaviorEvent(be) => match be {
                 BehaviorEvent::StartBehavior(sb) => {
-                    if let Some(event) = sb.on_activate() {
-                        self.event_queue.push_back(event);
-                    }
-
-                    if let Some(dur) = sb.get_duration() {
-                        self.timer_events
-                            .insert(TimerEvent::Behavior(BehaviorTimeoutEvent {
-                                behavior_id: event.behavior_id,
-                                instant: self.timer.add_duration(dur),
-                            }));
+                    // Simple behaviors have no timeouts, those are scheduled by `key_down` for the
+                    // behavior bound to the key
+                    if let Some(event) = sb.on_activate(event.behavior_id) {
+                        self.push_event(event)?;
                     }
                 }
                 BehaviorEvent::EndBehavior(sb) => {
-                    if let Some(event) = sb.on_deactivate() {
-                        self.event_queue.push_back(event);
+                    if let Some(event) = sb.on_deactivate(event.behavior_id) {
+                        self.push_event(event)?;
                     }
-
-                    // Remove from held behaviors list,
-                    self.held_behaviors.remove(&event.behavior_id);
                 }
-                BehaviorEvent::TapBehavior(sb) => {
-                    self.event_queue.push_back(Event::new(
+                BehaviorEvent::TapBehavior(sb, tap_duration) => {
+                    self.push_event(Event::new(
                         event.behavior_id,
                         EventData::BehaviorEvent(BehaviorEvent::StartBehavior(sb)),
-                    ));
+                    ))?;
 
-                    self.timer_events
-                        .insert(TimerEvent::UntapBehavior(UntapBehaviorEvent {
-                            behavior_id: event.behavior_id,
-                            instant: self.timer.add_duration(Duration::from_millis(100)),
-                        }));
+                    self.schedule(TimerEvent::UntapBehavior(UntapBehaviorEvent {
+                        behavior_id: event.behavior_id,
+                        behavior: sb,
+                        instant: self.now + tap_duration.unwrap_or(self.config.tap_duration),
+                    }))?;
                 }
                 BehaviorEvent::ReleasePressBehavior(sb, sb2) => {
-                    self.event_queue.push_back(Event::new(
+                    self.push_event(Event::new(
                         event.behavior_id,
                         EventData::BehaviorEvent(BehaviorEvent::EndBehavior(sb)),
-                    ));
+                    ))?;
 
-                    self.event_queue.push_back(Event::new(
+                    self.push_event(Event::new(
                         event.behavior_id,
-                        EventData::BehaviorEvent(BehaviorEvent::EndBehavior(sb2)),
-                    ));
+                        EventData::BehaviorEvent(BehaviorEvent::StartBehavior(sb2)),
+                    ))?;
                 }
-                BehaviorEvent::ReleaseTapBehavior(sb, sb2) => {
-                    self.event_queue.push_back(Event::new(
+                BehaviorEvent::ReleaseTapBehavior(sb, sb2, tap_duration) => {
+                    self.push_event(Event::new(
                         event.behavior_id,
                         EventData::BehaviorEvent(BehaviorEvent::EndBehavior(sb)),
-                    ));
+                    ))?;
 
-                    self.event_queue.push_back(Event::new(
+                    self.push_event(Event::new(
                         event.behavior_id,
-                        EventData::BehaviorEvent(BehaviorEvent::TapBehavior(sb2)),
-                    ));
+                        EventData::BehaviorEvent(BehaviorEvent::TapBehavior(sb2, tap_duration)),
+                    ))?;
+                }
+                BehaviorEvent::TapManualBehavior(mb) => self.tap_detached(mb.clone())?,
+            },
+            EventData::LayerEvent(le) => match le {
+                LayerEvent::AddLayer(layer) => {
+                    self.layers.push(layer).map_err(|_| Error::LayerStackFull)?;
                 }
+                LayerEvent::RemoveToLayer(layer) => self.layers.remove_to(layer),
+                LayerEvent::RemoveLayer(layer) => self.layers.remove(layer),
             },
-            EventData::LayerEvent(layer_event) => todo!(),
         }
+
+        Ok(())
     }
 
-    pub fn apply_timer_event(&mut self, event: TimerEvent) {
+    fn apply_timer_event(&mut self, event: TimerEvent) -> Result<(), Error> {
+        self.now = event.instant();
         match event {
             TimerEvent::Behavior(e) => {
-                if let Some(b) = self.held_behaviors.get_mut(&e.behavior_id) {
-                    if let Some(event) = b.on_timeout() {
-                        self.event_queue.push_back(event);
+                let id = e.behavior_id;
+                // Nothing to do if the behavior is gone, or an earlier timeout replaced this event
+                if self.behaviors.scheduled(id) != Some(e.instant) {
+                    return Ok(());
+                }
+                self.behaviors.set_scheduled(id, None);
+                match self.behaviors.deadline(id) {
+                    Some(deadline) if deadline == e.instant => {}
+                    // Pushed back since this was scheduled
+                    Some(deadline) if deadline > e.instant => {
+                        return self.schedule_timeout(id, deadline);
                     }
+                    // Disarmed
+                    _ => return Ok(()),
+                }
+
+                self.behaviors.set_deadline(id, None);
+                if let Some(b) = self.behaviors.get_mut(id)
+                    && let Some(event) = b.on_timeout(id)?
+                {
+                    self.push_event(event)?;
                 }
+                self.arm_timeout(id, e.instant)?;
+
+                self.drop_if_done(id)?;
+
+                // The timeout may have decided the behavior
+                self.replay_buffered()?;
             }
             TimerEvent::UntapKey(e) => {
-                self.keyboard_state
-                    .held_keys
-                    .remove_by(|(k, id)| *k == e.key && *id == 0);
+                self.unpress_code(e.key, e.behavior_id)?;
+            }
+            TimerEvent::Combo(e) => {
+                // Stale if the held back keys were resolved some other way since
+                if self.combo_deadline == Some(e.instant) {
+                    self.resolve_combo_keys()?;
+                }
             }
             TimerEvent::UntapBehavior(e) => {
-                self.event_queue.push_back(Event::new(e.behavior_id, EventData::BehaviorEvent(BehaviorEvent::EndBehavior()));
+                self.push_event(Event::new(
+                    e.behavior_id,
+                    EventData::BehaviorEvent(BehaviorEvent::EndBehavior(e.behavior)),
+                ))?;
+            }
+            TimerEvent::UntapManual(e) => {
+                self.unpress_behavior(
+                    e.behavior_id,
+                    DETACHED_POSITION,
+                    self.layers.top(),
+                    e.instant,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        behavior::{HoldTapBehavior, KeyPressBehavior, SimpleBehavior},
+        keymap::Binding,
+        sim::VirtualTimer,
+    };
+
+    /// State with one layer, position `i` presses `keys[i]`
+    fn key_state<const POSITIONS: usize>(
+        keys: [Key; POSITIONS],
+    ) -> State<VirtualTimer, 1, POSITIONS> {
+        let keymap = Keymap::new([keys.map(|key| {
+            Binding::Behavior(ManualBehavior::Simple(SimpleBehavior::KeyPress(
+                KeyPressBehavior::new(key),
+            )))
+        })]);
+        State::new(VirtualTimer::new(), keymap, EngineConfig::new())
+    }
+
+    /// Send every pending report to the host, in order
+    fn send_reports<const POSITIONS: usize>(
+        state: &mut State<VirtualTimer, 1, POSITIONS>,
+    ) -> std::vec::Vec<KeyboardReport> {
+        let mut sent = std::vec::Vec::new();
+        while let Some(report) = state.report() {
+            state.mark_report_sent(report);
+            sent.push(report);
+        }
+        sent
+    }
+
+    #[test]
+    fn report_queue_overflow_releases_everything() {
+        let mut state = key_state([Key::A]);
+
+        // Every tap queues a press and a release report, none of them are sent
+        let mut res = Ok(None);
+        for i in 0..MAX_PENDING_REPORTS as u64 {
+            res = state
+                .key_down(0, Instant::from_millis(2 * i))
+                .and_then(|_| state.poll())
+                .and_then(|_| state.key_up(0, Instant::from_millis(2 * i + 1)))
+                .and_then(|_| state.poll());
+            if res.is_err() {
+                break;
             }
         }
+        assert_eq!(res, Err(Error::ReportQueueFull));
+
+        // Nothing was dropped, and the host still ends up with nothing pressed
+        let sent = send_reports(&mut state);
+        assert_eq!(sent.len(), MAX_PENDING_REPORTS);
+        assert!(sent.last().is_some_and(|r| r.is_empty()));
+    }
+
+    #[test]
+    fn report_mode_switch_sends_empty_old_report_first() {
+        let mut state = key_state([Key::A, Key::B]);
+        state.key_down(0, Instant::from_millis(0)).unwrap();
+        state.poll().unwrap();
+        send_reports(&mut state);
+
+        // The host still thinks A is held
+        state.set_report_mode(ReportMode::Nkro);
+        assert_eq!(
+            state.report(),
+            Some(KeyboardReport::empty(ReportMode::Boot))
+        );
+
+        // Held back until the host saw the empty report
+        state.key_down(1, Instant::from_millis(10)).unwrap();
+        state.poll().unwrap();
+        let sent = send_reports(&mut state);
+
+        assert_eq!(state.report_mode(), ReportMode::Nkro);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], KeyboardReport::empty(ReportMode::Boot));
+        assert_eq!(sent[1].mode(), ReportMode::Nkro);
+        assert!(sent[1].is_pressed(Key::B) && !sent[1].is_pressed(Key::A));
+    }
+
+    #[test]
+    fn report_mode_switch_keeps_pending_reports() {
+        let mut state = key_state([Key::A]);
+        state.key_down(0, Instant::from_millis(0)).unwrap();
+        state.key_up(0, Instant::from_millis(10)).unwrap();
+        state.poll().unwrap();
+
+        // Neither report of the tap was sent yet
+        state.set_report_mode(ReportMode::Nkro);
+        let sent = send_reports(&mut state);
+
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].is_pressed(Key::A));
+        assert!(sent.iter().all(|r| r.mode() == ReportMode::Boot));
+        assert_eq!(state.report_mode(), ReportMode::Nkro);
+    }
+
+    #[test]
+    fn taps_end_relative_to_their_event() {
+        let timer = VirtualTimer::new();
+        let kp = |key| SimpleBehavior::KeyPress(KeyPressBehavior::new(key));
+        let ht = HoldTapBehavior::new(kp(Key::LeftShift), kp(Key::A));
+        let keymap = Keymap::new([[Binding::Behavior(ManualBehavior::HoldTap(ht))]]);
+        let mut state = State::new(timer.clone(), keymap, EngineConfig::new());
+
+        state.key_down(0, Instant::from_millis(0)).unwrap();
+        state.key_up(0, Instant::from_millis(50)).unwrap();
+        // Polled late, the tap still lasts the tap duration from the release
+        timer.set(Instant::from_millis(80));
+        state.poll().unwrap();
+
+        assert!(state.report().is_some_and(|r| r.is_pressed(Key::A)));
+        assert_eq!(
+            state.next_deadline(),
+            Some(Instant::from_millis(50) + EngineConfig::new().tap_duration)
+        );
     }
 }
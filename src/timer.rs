@@ -1,6 +1,9 @@
-use core::cmp::Ordering;
+use core::{
+    cmp::Ordering,
+    ops::{Add, Sub},
+};
 
-use crate::vboard::Key;
+use crate::{behavior::SimpleBehavior, registry::BehaviorId, vboard::KeyCode};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Duration {
@@ -8,35 +11,40 @@ pub struct Duration {
 }
 
 impl Duration {
-    pub fn from_micros(micros: u64) -> Self {
+    pub const fn from_micros(micros: u64) -> Self {
         Self {
             microseconds: micros,
         }
     }
 
-    pub fn from_millis(millis: u64) -> Self {
+    pub const fn from_millis(millis: u64) -> Self {
         Self {
             microseconds: millis * 1000,
         }
     }
 
-    pub fn from_secs(secs: u64) -> Self {
+    pub const fn from_secs(secs: u64) -> Self {
         Self {
             microseconds: secs * 1_000_000,
         }
     }
 
-    pub fn micros(&self) -> u64 {
+    pub const fn micros(&self) -> u64 {
         self.microseconds
     }
 
-    pub fn millis(&self) -> u64 {
+    pub const fn millis(&self) -> u64 {
         self.microseconds / 1000
     }
 
-    pub fn secs(&self) -> u64 {
+    pub const fn secs(&self) -> u64 {
         self.microseconds / 1_000_000
     }
+
+    /// `self - rhs`, or zero if `rhs` is longer
+    pub const fn saturating_sub(self, rhs: Duration) -> Duration {
+        Duration::from_micros(self.microseconds.saturating_sub(rhs.microseconds))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -45,37 +53,62 @@ pub struct Instant {
 }
 
 impl Instant {
-    pub fn from_micros(micros: u64) -> Self {
+    pub const fn from_micros(micros: u64) -> Self {
         Self {
             microseconds: micros,
         }
     }
 
-    pub fn from_millis(millis: u64) -> Self {
+    pub const fn from_millis(millis: u64) -> Self {
         Self {
             microseconds: millis * 1000,
         }
     }
 
-    pub fn from_secs(secs: u64) -> Self {
+    pub const fn from_secs(secs: u64) -> Self {
         Self {
             microseconds: secs * 1_000_000,
         }
     }
 
-    pub fn micros(&self) -> u64 {
+    pub const fn micros(&self) -> u64 {
         self.microseconds
     }
 
-    pub fn millis(&self) -> u64 {
+    pub const fn millis(&self) -> u64 {
         self.microseconds / 1000
     }
 
-    pub fn secs(&self) -> u64 {
+    pub const fn secs(&self) -> u64 {
         self.microseconds / 1_000_000
     }
 }
 
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Duration::from_micros(self.microseconds + rhs.microseconds)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant::from_micros(self.microseconds + rhs.microseconds)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    /// Saturates at zero if `rhs` is later than `self`
+    fn sub(self, rhs: Instant) -> Self::Output {
+        Duration::from_micros(self.microseconds.saturating_sub(rhs.microseconds))
+    }
+}
+
 pub trait Timer {
     fn as_instant(&self) -> Instant;
     fn add_duration(&self, duration: Duration) -> Instant;
@@ -87,6 +120,8 @@ pub enum TimerEvent {
     /// System-created key release event
     UntapKey(UntapKeyEvent),
     UntapBehavior(UntapBehaviorEvent),
+    Combo(ComboTimeoutEvent),
+    UntapManual(UntapManualEvent),
 }
 
 impl TimerEvent {
@@ -95,6 +130,8 @@ impl TimerEvent {
             TimerEvent::Behavior(e) => e.instant,
             Self::UntapKey(t) => t.instant,
             Self::UntapBehavior(t) => t.instant,
+            Self::Combo(t) => t.instant,
+            Self::UntapManual(t) => t.instant,
         }
     }
 }
@@ -119,11 +156,15 @@ impl Ord for TimerEvent {
             TimerEvent::Behavior(e) => e.instant,
             Self::UntapKey(t) => t.instant,
             Self::UntapBehavior(t) => t.instant,
+            Self::Combo(t) => t.instant,
+            Self::UntapManual(t) => t.instant,
         };
         let rhs_inst = match other {
             TimerEvent::Behavior(e) => e.instant,
             Self::UntapKey(t) => t.instant,
             Self::UntapBehavior(t) => t.instant,
+            Self::Combo(t) => t.instant,
+            Self::UntapManual(t) => t.instant,
         };
 
         lhs_inst.cmp(&rhs_inst)
@@ -131,7 +172,7 @@ impl Ord for TimerEvent {
 }
 
 pub struct BehaviorTimeoutEvent {
-    pub behavior_id: usize,
+    pub behavior_id: BehaviorId,
     pub instant: Instant,
 }
 
@@ -142,7 +183,9 @@ impl PartialEq for BehaviorTimeoutEvent {
 }
 
 pub struct UntapBehaviorEvent {
-    pub behavior_id: usize,
+    pub behavior_id: BehaviorId,
+    /// The tapped behavior, which is ended when this fires
+    pub behavior: SimpleBehavior,
     pub instant: Instant,
 }
 
@@ -153,7 +196,9 @@ impl PartialEq for UntapBehaviorEvent {
 }
 
 pub struct UntapKeyEvent {
-    pub key: Key,
+    pub key: KeyCode,
+    /// The behavior that tapped the key, only its press of `key` is released
+    pub behavior_id: BehaviorId,
     pub instant: Instant,
 }
 
@@ -162,3 +207,27 @@ impl PartialEq for UntapKeyEvent {
         self.instant == other.instant
     }
 }
+
+/// Held back combo keys stop waiting for the rest of their combo
+pub struct ComboTimeoutEvent {
+    pub instant: Instant,
+}
+
+impl PartialEq for ComboTimeoutEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.instant == other.instant
+    }
+}
+
+/// A manual behavior that was tapped by another behavior is released, see
+/// `BehaviorEvent::TapManualBehavior`
+pub struct UntapManualEvent {
+    pub behavior_id: BehaviorId,
+    pub instant: Instant,
+}
+
+impl PartialEq for UntapManualEvent {
+    fn eq(&self, other: &Self) -> bool {
+        self.instant == other.instant
+    }
+}
@@ -1,11 +1,72 @@
+use core::ops::BitOr;
+
 use num_enum::IntoPrimitive;
 use static_collections::List;
 
+use crate::{error::Error, registry::BehaviorId};
+
 pub const MAX_HELD_KEYS: usize = 20;
 
 pub struct KeyboardState {
     // Holds the keys that are held, and the manual behavior that caused it.
-    pub held_keys: List<(Key, usize), MAX_HELD_KEYS>,
+    pub held_keys: List<(Key, BehaviorId), MAX_HELD_KEYS>,
+}
+
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyboardState {
+    pub fn new() -> Self {
+        Self {
+            held_keys: List::new(),
+        }
+    }
+
+    pub fn press(&mut self, key: Key, behavior_id: BehaviorId) -> Result<(), Error> {
+        self.held_keys
+            .push_back((key, behavior_id))
+            .map_err(|_| Error::TooManyHeldKeys)
+    }
+
+    /// Release one press of `key` by `behavior_id`. The same key held by another behavior, e.g.
+    /// a physically held shift, stays held.
+    pub fn release(&mut self, key: Key, behavior_id: BehaviorId) {
+        self.held_keys
+            .remove_by(|(k, id)| *k == key && *id == behavior_id);
+    }
+
+    /// Press the modifiers of `code`, then its key
+    pub fn press_code(&mut self, code: KeyCode, behavior_id: BehaviorId) -> Result<(), Error> {
+        for key in code.modifiers.keys() {
+            self.press(key, behavior_id)?;
+        }
+        self.press(code.key, behavior_id)
+    }
+
+    /// Release the key of `code`, then its modifiers
+    pub fn release_code(&mut self, code: KeyCode, behavior_id: BehaviorId) {
+        self.release(code.key, behavior_id);
+        for key in code.modifiers.keys() {
+            self.release(key, behavior_id);
+        }
+    }
+
+    pub fn release_all(&mut self) {
+        self.held_keys = List::new();
+    }
+
+    /// The modifier keys that are held, by any behavior
+    pub fn modifiers(&self) -> Modifiers {
+        self.held_keys
+            .iter()
+            .filter_map(|(key, _)| key.modifier_bit())
+            .fold(Modifiers::NONE, |mods, bit| {
+                mods | Modifiers::from_bits(bit)
+            })
+    }
 }
 
 pub const MAX_HELD_KEYS_PER_B: usize = 5;
@@ -188,3 +249,130 @@ pub enum Key {
     RightGUI = 0xE7,
     //0xE8-0xFFFF Reserved
 }
+
+impl Key {
+    /// Bit for this key in the report modifier byte, `None` if it isn't a modifier
+    pub fn modifier_bit(&self) -> Option<u8> {
+        let usage: u8 = (*self).into();
+        if (u8::from(Key::LeftControl)..=u8::from(Key::RightGUI)).contains(&usage) {
+            Some(1 << (usage - u8::from(Key::LeftControl)))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_modifier(&self) -> bool {
+        self.modifier_bit().is_some()
+    }
+
+    /// Whether the key counts for key repeat unless `State::set_repeatable` says otherwise, i.e.
+    /// anything but a modifier on its own
+    pub fn is_repeatable(&self) -> bool {
+        !self.is_modifier()
+    }
+
+    /// Whether this is one of the letters `A` to `Z`
+    pub fn is_alpha(&self) -> bool {
+        (u8::from(Key::A)..=u8::from(Key::Z)).contains(&(*self).into())
+    }
+}
+
+/// Set of modifier keys, with the same bit layout as the modifier byte of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const LEFT_CTRL: Self = Self(0x01);
+    pub const LEFT_SHIFT: Self = Self(0x02);
+    pub const LEFT_ALT: Self = Self(0x04);
+    pub const LEFT_GUI: Self = Self(0x08);
+    pub const RIGHT_CTRL: Self = Self(0x10);
+    pub const RIGHT_SHIFT: Self = Self(0x20);
+    pub const RIGHT_ALT: Self = Self(0x40);
+    pub const RIGHT_GUI: Self = Self(0x80);
+    /// Left control, shift and alt
+    pub const MEH: Self = Self(0x07);
+    /// Left control, shift, alt and GUI
+    pub const HYPER: Self = Self(0x0F);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// The modifiers in `self` that aren't in `other`
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The modifier keys in the set, in report bit order
+    pub fn keys(self) -> impl Iterator<Item = Key> {
+        MODIFIER_KEYS
+            .into_iter()
+            .filter(move |k| k.modifier_bit().is_some_and(|bit| self.0 & bit != 0))
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+const MODIFIER_KEYS: [Key; 8] = [
+    Key::LeftControl,
+    Key::LeftShift,
+    Key::LeftAlt,
+    Key::LeftGUI,
+    Key::RightControl,
+    Key::RightShift,
+    Key::RightAlt,
+    Key::RightGUI,
+];
+
+/// A key along with modifiers that are held while it is, e.g. `!` is
+/// `KeyCode::new(Key::N1).with_modifiers(Modifiers::LEFT_SHIFT)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCode {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl KeyCode {
+    pub const fn new(key: Key) -> Self {
+        Self {
+            key,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    /// Adds `modifiers` to the ones already set
+    pub const fn with_modifiers(mut self, modifiers: Modifiers) -> Self {
+        self.modifiers = self.modifiers.union(modifiers);
+        self
+    }
+}
+
+impl From<Key> for KeyCode {
+    fn from(key: Key) -> Self {
+        Self::new(key)
+    }
+}